@@ -0,0 +1,440 @@
+//! Turns backtraces into `BacktraceInfo` frames.
+//!
+//! Frames captured with the [`backtrace`](https://docs.rs/backtrace) crate are read
+//! symbol by symbol, so inlined functions get their own entry and function names are
+//! demangled. `std::backtrace::Backtrace` does not expose the symbols of its frames,
+//! not even on nightly, so the backtrace of an `anyhow::Error`, which is a std one, is
+//! still read from its `Display` output. Capture a `backtrace::Backtrace` where the
+//! error is created and attach it with `ErrorInfo::with_backtrace` to avoid that.
+
+use crate::{BacktraceInfo, Config};
+use std::backtrace::BacktraceStatus;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Functions of the panic machinery and of the crates that capture backtraces, which
+/// sit on top of the frames of interest.
+const RUNTIME_FUNCTIONS: &[&str] = &[
+    "std::panicking::",
+    "core::panicking::",
+    "std::panic::",
+    "core::panic::",
+    "std::rt::",
+    "std::backtrace::",
+    "std::backtrace_rs::",
+    "std::sys::backtrace::",
+    "std::sys_common::backtrace::",
+    "core::result::unwrap_failed",
+    "core::option::unwrap_failed",
+    "core::option::expect_failed",
+    "<alloc::boxed::Box<F,A> as core::ops::function::Fn",
+    "rust_begin_unwind",
+    "__rust_",
+    "backtrace::",
+    "anyhow::",
+    "<anyhow::",
+    "errbit::",
+    "<errbit::",
+];
+
+/// Frames of a backtrace captured with the `backtrace` crate, resolving symbols first
+/// if it was captured with `Backtrace::new_unresolved`.
+pub fn from_backtrace(backtrace: &::backtrace::Backtrace) -> Vec<BacktraceInfo> {
+    let unresolved = backtrace
+        .frames()
+        .iter()
+        .any(|frame| frame.symbols().is_empty());
+    if unresolved {
+        let mut backtrace = backtrace.clone();
+        backtrace.resolve();
+        return collect_frames(&backtrace);
+    }
+    collect_frames(backtrace)
+}
+
+fn collect_frames(backtrace: &::backtrace::Backtrace) -> Vec<BacktraceInfo> {
+    backtrace
+        .frames()
+        .iter()
+        .flat_map(|frame| frame.symbols())
+        .map(|symbol| BacktraceInfo {
+            file: symbol.filename().map(|file| format!("{}", file.display())),
+            // the alternate form leaves out the trailing `::h<hash>`
+            function: symbol.name().map(|name| format!("{name:#}")),
+            line: symbol.lineno().map(|line| line as usize),
+            column: symbol.colno().map(|column| column as usize),
+            code: None,
+            in_app: None,
+        })
+        .filter(|frame| !frame.is_empty())
+        .collect()
+}
+
+/// Captures the frames leading to the caller, leaving out the ones inside errbit.
+pub fn capture() -> Vec<BacktraceInfo> {
+    let frames = from_backtrace(&::backtrace::Backtrace::new());
+    let internal = frames
+        .iter()
+        .take_while(|frame| frame.function.as_deref().is_none_or(is_internal))
+        .count();
+    frames.into_iter().skip(internal).collect()
+}
+
+/// Like `capture`, but only if backtraces are enabled the way they are for
+/// `std::backtrace::Backtrace::capture` and `anyhow`: by `RUST_LIB_BACKTRACE`, or
+/// else `RUST_BACKTRACE`, set to anything but `0`.
+pub fn capture_if_enabled() -> Option<Vec<BacktraceInfo>> {
+    if enabled() {
+        Some(capture())
+    } else {
+        None
+    }
+}
+
+fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        std::env::var_os("RUST_LIB_BACKTRACE")
+            .or_else(|| std::env::var_os("RUST_BACKTRACE"))
+            .is_some_and(|value| value != "0")
+    })
+}
+
+fn is_internal(function: &str) -> bool {
+    ["backtrace::", "errbit::", "<errbit::"]
+        .iter()
+        .any(|prefix| function.starts_with(prefix))
+}
+
+/// Frames of a `std::backtrace::Backtrace`, e.g. the one held by an `anyhow::Error`,
+/// read with `parse_display_fallback` since it has no other way to get at them.
+///
+/// `None` for a disabled or unsupported backtrace.
+pub fn from_std_backtrace(backtrace: &std::backtrace::Backtrace) -> Option<Vec<BacktraceInfo>> {
+    if backtrace.status() != BacktraceStatus::Captured {
+        return None;
+    }
+    Some(parse_display_fallback(&format!("{backtrace}")))
+}
+
+/// The backtrace a std error provides through `Error::provide`, if any: the frames of
+/// a `backtrace::Backtrace` if it provides one, or else those of a std one.
+#[cfg(feature = "nightly")]
+pub fn from_std_error(error: &(dyn std::error::Error + 'static)) -> Option<Vec<BacktraceInfo>> {
+    std::error::request_ref::<::backtrace::Backtrace>(error)
+        .map(from_backtrace)
+        .or_else(|| {
+            std::error::request_ref::<std::backtrace::Backtrace>(error).and_then(from_std_backtrace)
+        })
+}
+
+/// Tags the frames as application code or not, rewrites their paths relative to
+/// `[PROJECT_ROOT]` or `[CARGO]` and, if configured, trims the runtime frames on top.
+pub(crate) fn tidy(frames: &mut Vec<BacktraceInfo>, config: &Config) {
+    let root = config.app_root_directory.as_deref().map(Path::new);
+    for frame in frames.iter_mut() {
+        frame.file = frame.file.as_deref().map(|file| normalize_path(file, root));
+        let in_root = frame
+            .file
+            .as_deref()
+            .is_some_and(|file| file.starts_with("[PROJECT_ROOT]/"));
+        let in_app_crate = frame.function.as_deref().is_some_and(|function| {
+            let function = function.trim_start_matches('<');
+            config
+                .in_app_prefixes
+                .iter()
+                .any(|prefix| function.starts_with(prefix.as_str()))
+        });
+        frame.in_app = Some(in_root || in_app_crate);
+    }
+    if config.trim_runtime_frames {
+        trim_runtime_frames(frames);
+    }
+}
+
+/// `[PROJECT_ROOT]/src/main.rs` for files under the root directory (or relative to it)
+/// and `[CARGO]/crate-x.y/src/lib.rs` for files of the cargo registry.
+fn normalize_path(file: &str, root: Option<&Path>) -> String {
+    if file.starts_with('[') {
+        return file.to_owned();
+    }
+    let unix = file.replace('\\', "/");
+    if let Some(index) = unix.find("/registry/src/") {
+        // the first directory is the registry index, e.g. `index.crates.io-6f17d22bba15001f`
+        let rest = &unix[index + "/registry/src/".len()..];
+        if let Some((_, path)) = rest.split_once('/') {
+            return format!("[CARGO]/{path}");
+        }
+    }
+    if let Some(index) = unix.find("/git/checkouts/") {
+        return format!("[CARGO]/git/{}", &unix[index + "/git/checkouts/".len()..]);
+    }
+    let root = match root {
+        Some(root) => root,
+        None => return file.to_owned(),
+    };
+    let path = Path::new(file);
+    let relative = if path.is_absolute() {
+        match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return file.to_owned(),
+        }
+    } else {
+        path
+    };
+    let components: Vec<String> = relative
+        .components()
+        .filter(|component| !matches!(component, std::path::Component::CurDir))
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    format!("[PROJECT_ROOT]/{}", components.join("/"))
+}
+
+/// Drops the runtime frames that precede the first frame of other code.
+fn trim_runtime_frames(frames: &mut Vec<BacktraceInfo>) {
+    let mut reached_code = false;
+    frames.retain(|frame| {
+        if reached_code {
+            return true;
+        }
+        match frame.function.as_deref() {
+            Some(function) if is_runtime(function) => false,
+            // a frame without a function, e.g. a panic location, is kept
+            Some(_) => {
+                reached_code = true;
+                true
+            }
+            None => true,
+        }
+    });
+}
+
+fn is_runtime(function: &str) -> bool {
+    RUNTIME_FUNCTIONS
+        .iter()
+        .any(|prefix| function.starts_with(prefix))
+}
+
+/// The fallback for backtraces that only expose their `Display` output, which reads
+/// frames in the format `std::backtrace::Backtrace` displays them:
+///
+/// ```text
+///    0: app::handler
+///              at ./src/handler.rs:10:5
+///       app::inlined_helper
+///              at C:\app\src\helper.rs:3:9
+///    1: <unknown>
+/// ```
+fn parse_display_fallback(backtrace: &str) -> Vec<BacktraceInfo> {
+    let mut frames = vec![];
+    let mut frame: Option<BacktraceInfo> = None;
+    for line in backtrace.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("note:") {
+            continue;
+        }
+        if let Some(position) = line.strip_prefix("at ") {
+            let mut current = frame.take().unwrap_or_default();
+            let (file, line, column) = parse_position(position);
+            current.file = Some(file.to_owned());
+            current.line = line;
+            current.column = column;
+            frames.push(current);
+            continue;
+        }
+        if let Some(previous) = frame.take() {
+            frames.push(previous);
+        }
+        let function = strip_frame_number(line);
+        frame = Some(BacktraceInfo {
+            function: match function {
+                "<unknown>" => None,
+                function => Some(function.to_owned()),
+            },
+            ..BacktraceInfo::default()
+        });
+    }
+    frames.extend(frame);
+    frames.retain(|frame| !frame.is_empty());
+    frames
+}
+
+/// `file:line:column` where the file itself may contain colons (`C:\...`).
+fn parse_position(position: &str) -> (&str, Option<usize>, Option<usize>) {
+    let mut parts = position.rsplitn(3, ':');
+    let last = parts.next().and_then(|part| part.parse::<usize>().ok());
+    let middle = parts.next();
+    match (last, middle.and_then(|part| part.parse::<usize>().ok())) {
+        (Some(column), Some(line)) => (parts.next().unwrap_or_default(), Some(line), Some(column)),
+        (Some(line), None) => (
+            position.rsplit_once(':').map_or(position, |(file, _)| file),
+            Some(line),
+            None,
+        ),
+        _ => (position, None, None),
+    }
+}
+
+fn strip_frame_number(line: &str) -> &str {
+    match line.split_once(": ") {
+        Some((number, function)) if number.chars().all(|c| c.is_ascii_digit()) => function,
+        _ => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        capture, from_backtrace, from_std_backtrace, normalize_path, parse_display_fallback, tidy,
+    };
+    use crate::{BacktraceInfo, Config};
+    use std::path::Path;
+
+    fn frame(function: Option<&str>, file: Option<&str>, line: Option<usize>) -> BacktraceInfo {
+        BacktraceInfo {
+            function: function.map(str::to_owned),
+            file: file.map(str::to_owned),
+            line,
+            ..BacktraceInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let backtrace = r"   0: app::handler
+             at ./src/handler.rs:10:5
+      app::inlined_helper
+             at C:\app\src\helper.rs:3:9
+   1: std::rt::lang_start
+             at /rustc/abc/library/std/src/rt.rs:7
+   2: <unknown>
+  10: main
+note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.";
+        let frames = parse_display_fallback(backtrace);
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].function.as_deref(), Some("app::handler"));
+        assert_eq!(frames[0].file.as_deref(), Some("./src/handler.rs"));
+        assert_eq!((frames[0].line, frames[0].column), (Some(10), Some(5)));
+        assert_eq!(frames[1].function.as_deref(), Some("app::inlined_helper"));
+        assert_eq!(frames[1].file.as_deref(), Some(r"C:\app\src\helper.rs"));
+        assert_eq!((frames[1].line, frames[1].column), (Some(3), Some(9)));
+        assert_eq!(
+            frames[2].file.as_deref(),
+            Some("/rustc/abc/library/std/src/rt.rs")
+        );
+        assert_eq!((frames[2].line, frames[2].column), (Some(7), None));
+        assert_eq!(frames[3].function.as_deref(), Some("main"));
+        assert!(from_std_backtrace(&std::backtrace::Backtrace::disabled()).is_none());
+    }
+
+    #[test]
+    fn test_from_backtrace() {
+        let frames = from_backtrace(&::backtrace::Backtrace::new_unresolved());
+        let this = frames
+            .iter()
+            .find(|frame| {
+                frame
+                    .function
+                    .as_deref()
+                    .is_some_and(|function| function.ends_with("test_from_backtrace"))
+            })
+            .unwrap();
+        assert!(!this.function.as_ref().unwrap().contains("::h"));
+        assert!(this.file.as_ref().unwrap().ends_with("backtrace.rs"));
+        assert!(this.line.is_some());
+        assert_ne!(
+            frame(None, None, None).is_empty(),
+            frame(Some("f"), None, None).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_capture() {
+        let frames = capture();
+        assert!(!frames.is_empty());
+        let first = frames[0].function.as_deref().unwrap_or_default();
+        assert!(!first.starts_with("errbit::") && !first.starts_with("backtrace::"));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let root = Some(Path::new("/home/app"));
+        assert_eq!(
+            normalize_path("/home/app/src/main.rs", root),
+            "[PROJECT_ROOT]/src/main.rs"
+        );
+        assert_eq!(
+            normalize_path("./src/main.rs", root),
+            "[PROJECT_ROOT]/src/main.rs"
+        );
+        assert_eq!(
+            normalize_path(
+                "/home/me/.cargo/registry/src/index.crates.io-6f17d22bba15001f/tokio-1.12.0/src/runtime/mod.rs",
+                root
+            ),
+            "[CARGO]/tokio-1.12.0/src/runtime/mod.rs"
+        );
+        assert_eq!(
+            normalize_path(
+                r"C:\Users\me\.cargo\registry\src\github.com-1ecc6299db9ec823\hyper-0.14.0\src\client.rs",
+                root
+            ),
+            "[CARGO]/hyper-0.14.0/src/client.rs"
+        );
+        assert_eq!(
+            normalize_path("/rustc/abc/library/std/src/rt.rs", root),
+            "/rustc/abc/library/std/src/rt.rs"
+        );
+        assert_eq!(
+            normalize_path("[PROJECT_ROOT]/src/main.rs", root),
+            "[PROJECT_ROOT]/src/main.rs"
+        );
+        assert_eq!(normalize_path("src/main.rs", None), "src/main.rs");
+    }
+
+    #[test]
+    fn test_tidy() {
+        let config = Config {
+            app_root_directory: Some("/home/app".to_owned()),
+            in_app_prefixes: vec!["app_core::".to_owned()],
+            trim_runtime_frames: true,
+            ..Config::default()
+        };
+        let mut frames = vec![
+            frame(None, Some("/home/app/src/main.rs"), Some(3)),
+            frame(Some("std::panicking::begin_panic_handler"), None, None),
+            frame(Some("core::panicking::panic_fmt"), None, None),
+            frame(Some("app::handler"), Some("/home/app/src/main.rs"), Some(3)),
+            frame(Some("<app_core::Store as app::Load>::load"), None, None),
+            frame(
+                Some("tokio::runtime::park::block_on"),
+                Some("/cargo/registry/src/index/tokio-1.12.0/src/lib.rs"),
+                Some(1),
+            ),
+            frame(Some("std::rt::lang_start"), None, None),
+        ];
+        tidy(&mut frames, &config);
+        let functions: Vec<Option<&str>> = frames
+            .iter()
+            .map(|frame| frame.function.as_deref())
+            .collect();
+        assert_eq!(
+            functions,
+            vec![
+                None,
+                Some("app::handler"),
+                Some("<app_core::Store as app::Load>::load"),
+                Some("tokio::runtime::park::block_on"),
+                Some("std::rt::lang_start"),
+            ]
+        );
+        let in_app: Vec<Option<bool>> = frames.iter().map(|frame| frame.in_app).collect();
+        assert_eq!(
+            in_app,
+            vec![Some(true), Some(true), Some(true), Some(false), Some(false)]
+        );
+        assert_eq!(
+            frames[3].file.as_deref(),
+            Some("[CARGO]/tokio-1.12.0/src/lib.rs")
+        );
+    }
+}
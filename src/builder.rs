@@ -0,0 +1,307 @@
+use crate::value::to_value;
+use crate::{
+    backtrace, BacktraceInfo, Config, Context, ErrorInfo, Notice, Notifier, NotifierInfo,
+    NotifyResult, Result, Severity, UserInfo,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Composes a notice step by step.
+///
+/// ```no_run
+/// # async fn run(notifier: errbit::Notifier, error: anyhow::Error) -> errbit::Result<()> {
+/// use errbit::{NoticeBuilder, Severity};
+///
+/// NoticeBuilder::from_anyhow_error(&error)
+///     .severity(Severity::WARNING)
+///     .component("billing")
+///     .param("invoice_id", 42)
+///     .send(&notifier)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct NoticeBuilder {
+    errors: Vec<ErrorInfo>,
+    context: Context,
+    params: Option<HashMap<String, Value>>,
+    session: Option<HashMap<String, Value>>,
+    environment: Option<HashMap<String, Value>>,
+    config: Option<Config>,
+}
+
+impl NoticeBuilder {
+    /// Starts from the `source()` chain of the error, with the backtrace of the call site
+    /// if backtraces are enabled (see `backtrace::capture_if_enabled`).
+    pub fn from_std_error<E: std::error::Error + ?Sized>(error: &E) -> Self {
+        let mut errors = ErrorInfo::chain_from_std_error(error, usize::MAX);
+        errors[0].backtrace = backtrace::capture_if_enabled();
+        Self::new(errors)
+    }
+
+    pub fn from_anyhow_error(error: &anyhow::Error) -> Self {
+        Self::new(ErrorInfo::chain_from_anyhow_error(error, usize::MAX))
+    }
+
+    /// Starts from a message that is not backed by an error value, with the backtrace
+    /// of the call site if backtraces are enabled (see `backtrace::capture_if_enabled`).
+    pub fn from_message<T: Into<String>, M: Into<String>>(type_: T, message: M) -> Self {
+        let mut builder = Self::message(type_, message);
+        builder.errors[0].backtrace = backtrace::capture_if_enabled();
+        builder
+    }
+
+    /// Like `from_message` but without a backtrace, for callers that know better where
+    /// the notice comes from and set it with `backtrace`.
+    pub fn message<T: Into<String>, M: Into<String>>(type_: T, message: M) -> Self {
+        Self::new(vec![ErrorInfo {
+            type_: type_.into(),
+            message: message.into(),
+            backtrace: None,
+        }])
+    }
+
+    fn new(errors: Vec<ErrorInfo>) -> Self {
+        Self {
+            errors,
+            context: Context::default(),
+            params: None,
+            session: None,
+            environment: None,
+            config: None,
+        }
+    }
+
+    /// Fills the context fields that are not set otherwise (environment, os, hostname,
+    /// version, ...) from the config and caps the error chain at its depth.
+    pub fn config(mut self, config: &Config) -> Self {
+        self.config = Some(config.clone());
+        self
+    }
+
+    /// Replaces the backtrace of the outermost error.
+    pub fn backtrace(mut self, frames: Vec<BacktraceInfo>) -> Self {
+        self.errors[0].backtrace = Some(frames);
+        self
+    }
+
+    /// Defaults to `ERROR`.
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.context.severity = Some(severity);
+        self
+    }
+
+    pub fn component<S: Into<String>>(mut self, component: S) -> Self {
+        self.context.component = Some(component.into());
+        self
+    }
+
+    pub fn action<S: Into<String>>(mut self, action: S) -> Self {
+        self.context.action = Some(action.into());
+        self
+    }
+
+    pub fn user(mut self, user: UserInfo) -> Self {
+        self.context.user = Some(user);
+        self
+    }
+
+    pub fn url<S: Into<String>>(mut self, url: S) -> Self {
+        self.context.url = Some(url.into());
+        self
+    }
+
+    pub fn route<S: Into<String>>(mut self, route: S) -> Self {
+        self.context.route = Some(route.into());
+        self
+    }
+
+    pub fn http_method<S: Into<String>>(mut self, http_method: S) -> Self {
+        self.context.http_method = Some(http_method.into());
+        self
+    }
+
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.context.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// The address of the client the request was made by.
+    pub fn user_addr<S: Into<String>>(mut self, user_addr: S) -> Self {
+        self.context.user_addr = Some(user_addr.into());
+        self
+    }
+
+    /// The address of the peer the request was received from, possibly a proxy.
+    pub fn remote_addr<S: Into<String>>(mut self, remote_addr: S) -> Self {
+        self.context.remote_addr = Some(remote_addr.into());
+        self
+    }
+
+    /// Sets `params[key]`. A value that cannot be serialized is replaced with the error.
+    pub fn param<K: Into<String>, T: Serialize>(mut self, key: K, value: T) -> Self {
+        insert(&mut self.params, key.into(), value);
+        self
+    }
+
+    /// Sets `session[key]`, like `param`.
+    pub fn session<K: Into<String>, T: Serialize>(mut self, key: K, value: T) -> Self {
+        insert(&mut self.session, key.into(), value);
+        self
+    }
+
+    /// Sets `environment[key]`, like `param`.
+    pub fn env<K: Into<String>, T: Serialize>(mut self, key: K, value: T) -> Self {
+        insert(&mut self.environment, key.into(), value);
+        self
+    }
+
+    pub fn build(self) -> Notice {
+        let mut errors = self.errors;
+        let base = match &self.config {
+            Some(config) => {
+                errors.truncate(config.max_error_chain_depth.max(1));
+                Context::new_from_config(config)
+            }
+            None => Context {
+                notifier: Some(NotifierInfo::default()),
+                ..Context::default()
+            },
+        };
+        let mut context = merge(self.context, base);
+        context.severity = context.severity.or(Some(Severity::ERROR));
+        Notice {
+            errors,
+            context,
+            environment: self.environment,
+            session: self.session,
+            params: self.params,
+        }
+    }
+
+    /// Builds the notice with the notifier's config, unless one was given, and sends it.
+    pub async fn send(self, notifier: &Notifier) -> Result<NotifyResult> {
+        let builder = match self.config {
+            Some(_) => self,
+            None => self.config(notifier.config()),
+        };
+        notifier.notify(builder.build()).await
+    }
+}
+
+fn insert<T: Serialize>(map: &mut Option<HashMap<String, Value>>, key: String, value: T) {
+    map.get_or_insert_with(HashMap::new)
+        .insert(key, to_value(value));
+}
+
+/// The fields set in `overrides`, and the others from `base`.
+fn merge(overrides: Context, base: Context) -> Context {
+    Context {
+        notifier: overrides.notifier.or(base.notifier),
+        environment: overrides.environment.or(base.environment),
+        severity: overrides.severity.or(base.severity),
+        component: overrides.component.or(base.component),
+        action: overrides.action.or(base.action),
+        os: overrides.os.or(base.os),
+        hostname: overrides.hostname.or(base.hostname),
+        language: overrides.language.or(base.language),
+        version: overrides.version.or(base.version),
+        url: overrides.url.or(base.url),
+        user_agent: overrides.user_agent.or(base.user_agent),
+        user_addr: overrides.user_addr.or(base.user_addr),
+        remote_addr: overrides.remote_addr.or(base.remote_addr),
+        root_directory: overrides.root_directory.or(base.root_directory),
+        user: overrides.user.or(base.user),
+        route: overrides.route.or(base.route),
+        http_method: overrides.http_method.or(base.http_method),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NoticeBuilder;
+    use crate::test_util::{created, StubServer};
+    use crate::{Config, Notifier, Result, Severity, UserInfo};
+    use serde_json::json;
+
+    #[test]
+    fn test_build() {
+        let config = Config {
+            environment: Some("production".to_owned()),
+            app_version: Some("1.2.3".to_owned()),
+            max_error_chain_depth: 1,
+            ..Config::default()
+        };
+        let error = anyhow::anyhow!("root cause").context("outer");
+        let notice = NoticeBuilder::from_anyhow_error(&error)
+            .config(&config)
+            .severity(Severity::WARNING)
+            .component("billing")
+            .action("charge")
+            .user(UserInfo {
+                id: Some("7".to_owned()),
+                ..UserInfo::default()
+            })
+            .url("https://example.com/charge")
+            .route("/charge")
+            .http_method("POST")
+            .param("body", json!({"amount": 10}))
+            .session("cart", vec![1, 2])
+            .env("REGION", "eu")
+            .build();
+        assert_eq!(notice.errors.len(), 1);
+        assert_eq!(notice.errors[0].message, "outer");
+        let context = &notice.context;
+        assert_eq!(context.severity, Some(Severity::WARNING));
+        assert_eq!(context.environment.as_deref(), Some("production"));
+        assert_eq!(context.version.as_deref(), Some("1.2.3"));
+        assert_eq!(context.component.as_deref(), Some("billing"));
+        assert_eq!(context.action.as_deref(), Some("charge"));
+        assert_eq!(context.user.as_ref().unwrap().id.as_deref(), Some("7"));
+        assert_eq!(context.route.as_deref(), Some("/charge"));
+        assert_eq!(context.http_method.as_deref(), Some("POST"));
+        assert!(context.notifier.is_some());
+        assert_eq!(notice.params.unwrap()["body"]["amount"], 10);
+        assert_eq!(notice.session.unwrap()["cart"], json!([1, 2]));
+        assert_eq!(notice.environment.unwrap()["REGION"], "eu");
+    }
+
+    #[test]
+    fn test_build_from_message() {
+        let notice = NoticeBuilder::from_message("UnexpectedState", "gateway said maybe").build();
+        assert_eq!(notice.errors[0].type_, "UnexpectedState");
+        assert_eq!(
+            notice.errors[0].backtrace.is_some(),
+            crate::backtrace::capture_if_enabled().is_some()
+        );
+        let notice = NoticeBuilder::message("UnexpectedState", "gateway said maybe").build();
+        assert!(notice.errors[0].backtrace.is_none());
+        assert_eq!(notice.context.severity, Some(Severity::ERROR));
+        assert!(notice.context.hostname.is_none());
+        let notice = NoticeBuilder::from_std_error(&std::fmt::Error).build();
+        assert_eq!(notice.errors[0].type_, "Error");
+        assert_eq!(
+            notice.errors[0].backtrace.is_some(),
+            crate::backtrace::capture_if_enabled().is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let mut config = server.config();
+        config.environment = Some("staging".to_owned());
+        let notifier = Notifier::new(config)?;
+        let result = NoticeBuilder::from_message("Check", "sent")
+            .component("jobs")
+            .send(&notifier)
+            .await?;
+        assert_eq!(result.id, "1");
+        let notice = server.notices()?.remove(0);
+        assert_eq!(notice.context.environment.as_deref(), Some("staging"));
+        assert_eq!(notice.context.component.as_deref(), Some("jobs"));
+        Ok(())
+    }
+}
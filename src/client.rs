@@ -1,11 +1,28 @@
-use crate::{Notice, NotifyResult, Result};
+use crate::{Error, Notice, NotifyResult, Result, RetryPolicy};
 use http::uri::InvalidUri;
 use hyper::Uri;
 use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+tokio::task_local! {
+    /// Set while a notice is being sent, including in the connection tasks of the client.
+    pub(crate) static SENDING: ();
+}
+
+/// Whether the current task is sending a notice, so that the logging integrations do
+/// not report what the transport logs meanwhile and recurse.
+#[cfg(any(feature = "log", feature = "tracing"))]
+pub(crate) fn sending() -> bool {
+    SENDING.try_with(|_| ()).is_ok()
+}
 
 #[derive(Debug, Clone)]
 pub struct Client {
     inner: sealed::HttpClient,
+    retry_policy: RetryPolicy,
+    /// Set from the `Retry-After` header of a `429` response and shared by all clones.
+    rate_limited_until: Arc<Mutex<Option<SystemTime>>>,
 }
 
 impl Client {
@@ -21,15 +38,100 @@ impl Client {
             } else {
                 sealed::HttpClient::new_http(url)
             },
+            retry_policy: RetryPolicy::default(),
+            rate_limited_until: Arc::new(Mutex::new(None)),
         })
     }
 
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// A client for the same endpoint with its own connection pool, for use from
+    /// another tokio runtime. Retry policy and rate limit window are shared.
+    pub fn with_fresh_connections(&self) -> Self {
+        Self {
+            inner: self.inner.with_fresh_connections(),
+            retry_policy: self.retry_policy.clone(),
+            rate_limited_until: self.rate_limited_until.clone(),
+        }
+    }
+
+    /// The time until which notices are rejected with `Error::RateLimited`
+    /// without contacting the server.
+    pub fn rate_limited_until(&self) -> Option<SystemTime> {
+        let until = *self.rate_limited_until.lock().unwrap();
+        until.filter(|until| *until > SystemTime::now())
+    }
+
     pub async fn notify(&self, notice: &Notice) -> Result<NotifyResult> {
-        self.inner.notify(notice).await
+        SENDING.scope((), self.send(notice)).await
+    }
+
+    async fn send(&self, notice: &Notice) -> Result<NotifyResult> {
+        let mut attempt = 1;
+        loop {
+            if let Some(until) = self.rate_limited_until() {
+                return Err(Error::RateLimited { until }.into());
+            }
+            let result = self
+                .inner
+                .notify(notice)
+                .await
+                .map_err(|e| self.rate_limit(e));
+            match result {
+                Err(e) if attempt < self.retry_policy.max_attempts => {
+                    match self.retry_delay(&e, attempt) {
+                        Some(delay) => tokio::time::sleep(delay).await,
+                        None => return Err(e),
+                    }
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// How long to wait before resending after the error, if it is worth it. A `429`
+    /// is only retried once the pause it started is over.
+    fn retry_delay(&self, error: &anyhow::Error, attempt: u32) -> Option<Duration> {
+        if !self.retry_policy.is_retryable(error) {
+            return None;
+        }
+        match error.downcast_ref::<Error>() {
+            Some(Error::RateLimited { until }) => {
+                let remaining = until.duration_since(SystemTime::now()).unwrap_or_default();
+                (remaining <= self.retry_policy.max_delay).then_some(remaining)
+            }
+            _ => Some(self.retry_policy.delay(attempt)),
+        }
+    }
+
+    /// Turns a `429` into `Error::RateLimited` and pauses until its `Retry-After`, or for
+    /// `rate_limit_pause` when it has none that can be read.
+    fn rate_limit(&self, error: anyhow::Error) -> anyhow::Error {
+        let until = match error.downcast_ref::<Error>() {
+            Some(Error::RateLimited { until }) => *until,
+            Some(Error::Gateway {
+                status_code: 429, ..
+            }) => SystemTime::now() + self.retry_policy.rate_limit_pause,
+            _ => return error,
+        };
+        self.pause_until(until);
+        Error::RateLimited { until }.into()
+    }
+
+    fn pause_until(&self, until: SystemTime) {
+        let mut rate_limited_until = self.rate_limited_until.lock().unwrap();
+        if !matches!(*rate_limited_until, Some(current) if current >= until) {
+            *rate_limited_until = Some(until);
+        }
     }
 }
 
 mod sealed {
+    use super::SENDING;
     use crate::{Error, Notice, NotifyResult, Result};
     use http::StatusCode;
     use hyper::body::Buf;
@@ -37,7 +139,24 @@ mod sealed {
     use hyper::client::HttpConnector;
     use hyper::{header, Uri};
     use hyper_rustls::HttpsConnector;
+    use std::future::Future;
     use std::io::Read;
+    use std::time::{Duration, SystemTime};
+
+    /// Spawns the connection tasks of hyper onto tokio like its default executor, marked
+    /// as sending.
+    #[derive(Debug, Clone, Copy)]
+    pub struct SendingExecutor;
+
+    impl<F> hyper::rt::Executor<F> for SendingExecutor
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        fn execute(&self, future: F) {
+            tokio::spawn(SENDING.scope((), future));
+        }
+    }
 
     #[derive(Debug, Clone)]
     pub struct HyperClient<C> {
@@ -64,7 +183,12 @@ mod sealed {
             let headers = request.headers_mut();
             headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
             let response = self.inner.request(request).await?;
-            let response_status = response.status().clone();
+            let response_status = response.status();
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
             let mut response_body = String::new();
             hyper::body::aggregate(response.into_body())
                 .await?
@@ -72,6 +196,10 @@ mod sealed {
                 .read_to_string(&mut response_body)?;
             if response_status == StatusCode::CREATED {
                 Ok(serde_json::from_str(&response_body)?)
+            } else if let (StatusCode::TOO_MANY_REQUESTS, Some(until)) =
+                (response_status, retry_after)
+            {
+                Err(Error::RateLimited { until }.into())
             } else {
                 Err(Error::Gateway {
                     status_code: response_status.as_u16(),
@@ -82,6 +210,16 @@ mod sealed {
         }
     }
 
+    /// `Retry-After` is either a number of seconds or an HTTP date. A number of seconds
+    /// too large to add to the current time counts as unreadable.
+    pub fn parse_retry_after(value: &str) -> Option<SystemTime> {
+        let value = value.trim();
+        match value.parse::<u64>() {
+            Ok(seconds) => SystemTime::now().checked_add(Duration::from_secs(seconds)),
+            Err(_) => httpdate::parse_http_date(value).ok(),
+        }
+    }
+
     #[derive(Debug, Clone)]
     pub enum HttpClient {
         Http(HyperClient<HttpConnector>),
@@ -90,14 +228,27 @@ mod sealed {
 
     impl HttpClient {
         pub fn new_http(uri: Uri) -> Self {
-            Self::Http(HyperClient::new(uri, hyper::Client::new()))
+            Self::Http(HyperClient::new(
+                uri,
+                hyper::Client::builder()
+                    .executor(SendingExecutor)
+                    .build_http(),
+            ))
         }
         pub fn new_https(uri: Uri) -> Self {
             Self::Https(HyperClient::new(
                 uri,
-                hyper::Client::builder().build(HttpsConnector::with_native_roots()),
+                hyper::Client::builder()
+                    .executor(SendingExecutor)
+                    .build(HttpsConnector::with_native_roots()),
             ))
         }
+        pub fn with_fresh_connections(&self) -> Self {
+            match self {
+                HttpClient::Http(c) => Self::new_http(c.uri.clone()),
+                HttpClient::Https(c) => Self::new_https(c.uri.clone()),
+            }
+        }
         pub async fn notify(&self, notice: &Notice) -> Result<NotifyResult> {
             match self {
                 HttpClient::Http(c) => c.notify(notice).await,
@@ -109,8 +260,36 @@ mod sealed {
 
 #[cfg(test)]
 mod tests {
+    use super::sealed::parse_retry_after;
     use super::Client;
-    use crate::{Config, Notice, Result};
+    use crate::test_util::{created, notice, status, StubServer};
+    use crate::{Config, Error, Notice, Result, RetryPolicy};
+    use hyper::{Body, Response};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn too_many_requests(retry_after: &str) -> Response<Body> {
+        Response::builder()
+            .status(429)
+            .header("Retry-After", retry_after)
+            .body(Body::from("slow down"))
+            .unwrap()
+    }
+
+    fn assert_rate_limited(err: &anyhow::Error) {
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::RateLimited { .. })
+        ));
+    }
+
+    fn retry_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            ..RetryPolicy::default()
+        }
+    }
 
     #[tokio::test]
     #[serial_test::serial]
@@ -128,4 +307,168 @@ mod tests {
         assert!(!result.id.is_empty());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_retry_until_success() -> Result<()> {
+        let server = StubServer::start(|n| if n < 2 { status(503) } else { created(n) }).await;
+        let config = server.config();
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(retry_policy(3));
+        let result = client.notify(&notice("retry test")).await?;
+        assert_eq!(result.id, "3");
+        assert_eq!(server.request_count(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() -> Result<()> {
+        let server = StubServer::start(|_| status(502)).await;
+        let config = server.config();
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(retry_policy(3));
+        let err = client.notify(&notice("retry test")).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Gateway {
+                status_code: 502,
+                ..
+            })
+        ));
+        assert_eq!(server.request_count(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_client_error() -> Result<()> {
+        let server = StubServer::start(|_| status(422)).await;
+        let config = server.config();
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(retry_policy(3));
+        assert!(client.notify(&notice("retry test")).await.is_err());
+        assert_eq!(server.request_count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_connection_error() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        drop(listener);
+        let config = Config {
+            host: format!("http://{addr}"),
+            ..Config::default()
+        };
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(retry_policy(2));
+        let err = client.notify(&notice("retry test")).await.unwrap_err();
+        assert!(retry_policy(2).is_retryable(&err));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let until = parse_retry_after("120").unwrap();
+        let remaining = until.duration_since(SystemTime::now()).unwrap();
+        assert!(remaining > Duration::from_secs(110) && remaining <= Duration::from_secs(120));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(1445412480))
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("18446744073709551615"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited() -> Result<()> {
+        let server = StubServer::start(|_| too_many_requests("60")).await;
+        let config = server.config();
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(retry_policy(3));
+        for _ in 0..10 {
+            let err = client.notify(&notice("retry test")).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::RateLimited { .. })
+            ));
+        }
+        assert_eq!(server.request_count(), 1);
+        assert!(client.clone().rate_limited_until().is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_window_passes() -> Result<()> {
+        let server = StubServer::start(|n| {
+            if n == 0 {
+                too_many_requests("1")
+            } else {
+                created(n)
+            }
+        })
+        .await;
+        let config = server.config();
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(retry_policy(3));
+        assert!(client.notify(&notice("retry test")).await.is_err());
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(client.rate_limited_until().is_none());
+        let result = client.notify(&notice("retry test")).await?;
+        assert_eq!(result.id, "2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_too_many_requests() -> Result<()> {
+        let server = StubServer::start(|n| match n {
+            0 => too_many_requests("soon"),
+            _ => created(n),
+        })
+        .await;
+        let config = server.config();
+        let policy = RetryPolicy {
+            retry_too_many_requests: true,
+            rate_limit_pause: Duration::from_millis(50),
+            max_delay: Duration::from_millis(100),
+            ..retry_policy(3)
+        };
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(policy.clone());
+        let started = std::time::Instant::now();
+        assert_eq!(client.notify(&notice("retry test")).await?.id, "2");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // a pause longer than `max_delay` is not waited for
+        let policy = RetryPolicy {
+            rate_limit_pause: Duration::from_secs(60),
+            ..policy
+        };
+        let server = StubServer::start(|_| status(429)).await;
+        let config = server.config();
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(policy);
+        assert_rate_limited(&client.notify(&notice("retry test")).await.unwrap_err());
+        assert_eq!(server.request_count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_without_retry_after() -> Result<()> {
+        let server = StubServer::start(|n| match n {
+            0 => status(429),
+            1 => too_many_requests("soon"),
+            _ => created(n),
+        })
+        .await;
+        let config = server.config();
+        let policy = RetryPolicy {
+            rate_limit_pause: Duration::from_millis(500),
+            ..retry_policy(3)
+        };
+        let client = Client::new(config.endpoint().as_str())?.with_retry_policy(policy);
+        for _ in 0..5 {
+            assert_rate_limited(&client.notify(&notice("retry test")).await.unwrap_err());
+        }
+        assert_eq!(server.request_count(), 1);
+        let until = client.rate_limited_until().unwrap();
+        assert!(until <= SystemTime::now() + Duration::from_millis(500));
+
+        // an unreadable `Retry-After` pauses for the default window as well
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_rate_limited(&client.notify(&notice("retry test")).await.unwrap_err());
+        assert_eq!(server.request_count(), 2);
+        assert!(client.rate_limited_until().is_some());
+        Ok(())
+    }
 }
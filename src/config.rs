@@ -1,3 +1,5 @@
+use crate::{QueueConfig, RetryPolicy, SourceContextConfig, SpoolConfig, ValueLimits};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Config {
     pub host: String,
@@ -10,6 +12,25 @@ pub struct Config {
     pub app_language: Option<String>,
     pub app_version: Option<String>,
     pub app_root_directory: Option<String>,
+
+    /// How many links of an error's `source()` chain are reported, outermost first.
+    pub max_error_chain_depth: usize,
+    /// Attach the surrounding lines of code to frames in files under `app_root_directory`.
+    /// Off by default.
+    pub source_context: Option<SourceContextConfig>,
+    /// Frames whose function starts with one of these paths, e.g. `my_app::`, count as
+    /// application code in addition to the ones in files under `app_root_directory`.
+    pub in_app_prefixes: Vec<String>,
+    /// Leave out the panic and backtrace machinery frames on top of each backtrace.
+    pub trim_runtime_frames: bool,
+    /// Bounds for the values of `params`, `session` and `environment`.
+    pub value_limits: ValueLimits,
+
+    /// Deliver notices from background workers instead of inline.
+    pub queue: Option<QueueConfig>,
+    pub retry: RetryPolicy,
+    /// Keep notices that could not be delivered on disk and resend them later.
+    pub spool: Option<SpoolConfig>,
 }
 
 impl Default for Config {
@@ -18,7 +39,7 @@ impl Default for Config {
         let project_id = std::env::var("AIRBRAKE_PROJECT_ID").unwrap_or("0".to_owned());
         let project_key = std::env::var("AIRBRAKE_API_KEY").unwrap_or("0".to_owned());
         let environment = std::env::var("AIRBRAKE_ENVIRONMENT")
-            .map(|inner| Some(inner))
+            .map(Some)
             .unwrap_or(None);
         let app_os = Some(std::env::consts::OS.to_owned());
         let app_hostname = hostname::get()
@@ -37,6 +58,14 @@ impl Default for Config {
             app_language: None,
             app_version: None,
             app_root_directory,
+            max_error_chain_depth: 10,
+            source_context: None,
+            in_app_prefixes: vec![],
+            trim_runtime_frames: false,
+            value_limits: ValueLimits::default(),
+            queue: None,
+            retry: RetryPolicy::default(),
+            spool: None,
         }
     }
 }
@@ -53,6 +82,7 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::Config;
+    use crate::{RetryPolicy, ValueLimits};
 
     #[test]
     #[serial_test::serial]
@@ -75,6 +105,14 @@ mod tests {
             app_language: None,
             app_version: None,
             app_root_directory,
+            max_error_chain_depth: 10,
+            source_context: None,
+            in_app_prefixes: vec![],
+            trim_runtime_frames: false,
+            value_limits: ValueLimits::default(),
+            queue: None,
+            retry: RetryPolicy::default(),
+            spool: None,
         };
         assert_eq!(expected, config);
         assert_eq!(
@@ -104,6 +142,14 @@ mod tests {
             app_language: None,
             app_version: None,
             app_root_directory,
+            max_error_chain_depth: 10,
+            source_context: None,
+            in_app_prefixes: vec![],
+            trim_runtime_frames: false,
+            value_limits: ValueLimits::default(),
+            queue: None,
+            retry: RetryPolicy::default(),
+            spool: None,
         };
         assert_eq!(expected, config);
         assert_eq!(
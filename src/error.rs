@@ -1,4 +1,5 @@
 pub use anyhow::Result;
+use std::time::{Duration, SystemTime};
 use thiserror::Error as ThisError;
 
 #[derive(ThisError, Debug)]
@@ -7,4 +8,16 @@ pub enum Error {
     IO { reason: String },
     #[error("API response error: [{status_code:?}]{reason:?}")]
     Gateway { status_code: u16, reason: String },
+    #[error("Rate limited until {until:?}")]
+    RateLimited { until: SystemTime },
+    #[error("Timed out after {timeout:?}")]
+    Timeout { timeout: Duration },
+    #[error("Runtime error: {reason:?}")]
+    Runtime { reason: String },
+    #[error("Notice dropped: {reason:?}")]
+    Dropped { reason: String },
+    #[error("Notice filtered out")]
+    Filtered,
+    #[error("The global notifier is already initialized")]
+    AlreadyInitialized,
 }
@@ -0,0 +1,260 @@
+use crate::{Notice, Result, Severity};
+use regex::Regex;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Changes a notice before it is sent, or drops it by returning `None`.
+///
+/// Closures taking and returning the notice are filters too:
+///
+/// ```
+/// # fn run(notifier: &errbit::Notifier) {
+/// notifier.add_filter(|mut notice: errbit::Notice| {
+///     notice.context.component = Some("billing".to_owned());
+///     Some(notice)
+/// });
+/// # }
+/// ```
+pub trait Filter: Send + Sync {
+    fn filter(&self, notice: Notice) -> Option<Notice>;
+}
+
+impl<F> Filter for F
+where
+    F: Fn(Notice) -> Option<Notice> + Send + Sync,
+{
+    fn filter(&self, notice: Notice) -> Option<Notice> {
+        self(notice)
+    }
+}
+
+/// The filters of a notifier, run in the order they were added.
+#[derive(Default)]
+pub(crate) struct Filters {
+    filters: RwLock<Vec<Arc<dyn Filter>>>,
+}
+
+impl Filters {
+    pub fn add(&self, filter: Arc<dyn Filter>) {
+        self.filters.write().unwrap().push(filter);
+    }
+
+    pub fn apply(&self, notice: Notice) -> Option<Notice> {
+        // released before running them, so a filter may add another one
+        let filters = self.filters.read().unwrap().clone();
+        filters
+            .iter()
+            .try_fold(notice, |notice, filter| filter.filter(notice))
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Filters")
+            .field("len", &self.filters.read().unwrap().len())
+            .finish()
+    }
+}
+
+/// Drops the notices of the given environments, e.g. `development` and `test`.
+#[derive(Debug, Clone)]
+pub struct IgnoreEnvironments {
+    environments: Vec<String>,
+}
+
+impl IgnoreEnvironments {
+    pub fn new<I, S>(environments: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            environments: environments.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Filter for IgnoreEnvironments {
+    fn filter(&self, notice: Notice) -> Option<Notice> {
+        match &notice.context.environment {
+            Some(environment) if self.environments.contains(environment) => None,
+            _ => Some(notice),
+        }
+    }
+}
+
+/// Drops the notices with an error, at any depth of the chain, whose type has one of
+/// the given names or matches one of the given patterns.
+#[derive(Debug, Clone)]
+pub struct IgnoreErrorTypes {
+    names: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl IgnoreErrorTypes {
+    pub fn new<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            names: names.into_iter().map(Into::into).collect(),
+            patterns: vec![],
+        }
+    }
+
+    /// Also drops the error types matching the regular expression.
+    pub fn with_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.patterns.push(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    fn is_ignored(&self, type_: &str) -> bool {
+        self.names.iter().any(|name| name == type_)
+            || self.patterns.iter().any(|pattern| pattern.is_match(type_))
+    }
+}
+
+impl Filter for IgnoreErrorTypes {
+    fn filter(&self, notice: Notice) -> Option<Notice> {
+        if notice
+            .errors
+            .iter()
+            .any(|error| self.is_ignored(&error.type_))
+        {
+            None
+        } else {
+            Some(notice)
+        }
+    }
+}
+
+/// Drops the notices less severe than the given severity. Notices without a severity
+/// count as `ERROR`.
+#[derive(Debug, Clone)]
+pub struct MinimumSeverity {
+    severity: Severity,
+}
+
+impl MinimumSeverity {
+    pub fn new(severity: Severity) -> Self {
+        Self { severity }
+    }
+}
+
+impl Filter for MinimumSeverity {
+    fn filter(&self, notice: Notice) -> Option<Notice> {
+        let severity = notice.context.severity.as_ref().unwrap_or(&Severity::ERROR);
+        match (rank(severity), rank(&self.severity)) {
+            (Some(severity), Some(minimum)) if severity < minimum => None,
+            _ => Some(notice),
+        }
+    }
+}
+
+fn rank(severity: &Severity) -> Option<u8> {
+    match severity {
+        Severity::DEBUG => Some(0),
+        Severity::INFO => Some(1),
+        Severity::NOTICE => Some(2),
+        Severity::WARNING => Some(3),
+        Severity::ERROR => Some(4),
+        Severity::CRITICAL => Some(5),
+        Severity::ALERT => Some(6),
+        Severity::EMERGENCY => Some(7),
+        Severity::INVALID => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Filters, IgnoreEnvironments, IgnoreErrorTypes, MinimumSeverity};
+    use crate::test_util::{created, notice, StubServer};
+    use crate::{Error, Notice, Notifier, Result, Severity};
+    use std::sync::Arc;
+
+    fn notice_in(environment: Option<&str>, severity: Option<Severity>) -> Notice {
+        let mut notice = notice("filter test");
+        notice.context.environment = environment.map(str::to_owned);
+        notice.context.severity = severity;
+        notice
+    }
+
+    #[test]
+    fn test_filters_run_in_order() {
+        let filters = Filters::default();
+        filters.add(Arc::new(|mut notice: Notice| {
+            notice.context.component = Some("first".to_owned());
+            Some(notice)
+        }));
+        filters.add(Arc::new(|mut notice: Notice| {
+            let component = notice.context.component.take().unwrap_or_default();
+            notice.context.component = Some(format!("{component} second"));
+            Some(notice)
+        }));
+        let notice = filters.apply(notice_in(None, None)).unwrap();
+        assert_eq!(notice.context.component.as_deref(), Some("first second"));
+        filters.add(Arc::new(|_: Notice| None));
+        assert!(filters.apply(self::notice_in(None, None)).is_none());
+    }
+
+    #[test]
+    fn test_ignore_environments() {
+        let filter = IgnoreEnvironments::new(vec!["development", "test"]);
+        assert!(filter.filter(notice_in(Some("test"), None)).is_none());
+        assert!(filter.filter(notice_in(Some("production"), None)).is_some());
+        assert!(filter.filter(notice_in(None, None)).is_some());
+    }
+
+    #[test]
+    fn test_ignore_error_types() -> Result<()> {
+        // a message-only error is reported as `Error`
+        let filter = IgnoreErrorTypes::new(vec!["Error"]);
+        assert!(filter.filter(notice_in(None, None)).is_none());
+        let filter = IgnoreErrorTypes::new(Vec::<String>::new()).with_pattern("^Err")?;
+        assert!(filter.filter(notice_in(None, None)).is_none());
+        let filter = IgnoreErrorTypes::new(vec!["ParseIntError"]).with_pattern("^Timeout")?;
+        assert!(filter.filter(notice_in(None, None)).is_some());
+        assert!(IgnoreErrorTypes::new(vec!["x"]).with_pattern("(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_minimum_severity() {
+        let filter = MinimumSeverity::new(Severity::WARNING);
+        assert!(filter
+            .filter(notice_in(None, Some(Severity::INFO)))
+            .is_none());
+        assert!(filter
+            .filter(notice_in(None, Some(Severity::WARNING)))
+            .is_some());
+        assert!(filter
+            .filter(notice_in(None, Some(Severity::CRITICAL)))
+            .is_some());
+        assert!(filter.filter(notice_in(None, None)).is_some());
+        let filter = MinimumSeverity::new(Severity::CRITICAL);
+        assert!(filter.filter(notice_in(None, None)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_notifier_filters() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        notifier.add_filter(MinimumSeverity::new(Severity::ERROR));
+        let err = notifier
+            .notify(notice_in(None, Some(Severity::DEBUG)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Filtered)));
+        assert!(notifier
+            .try_enqueue(notice_in(None, Some(Severity::DEBUG)))
+            .await
+            .is_err());
+        assert_eq!(server.request_count(), 0);
+        notifier
+            .notify_anyhow_error(&anyhow::anyhow!("kept"))
+            .await?;
+        assert_eq!(server.request_count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use crate::Notifier;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// What happened to background notices since the previous `Notifier::flush` or
+/// `Notifier::close` (or since the notifier was created), up to the end of this one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlushReport {
+    pub delivered: usize,
+    pub dropped: usize,
+    pub spooled: usize,
+    /// Notices still queued or in flight when the deadline passed.
+    pub pending: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Delivered,
+    Dropped,
+    Spooled,
+}
+
+/// Counts the notices handed over for background delivery until they are settled.
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    pending: Mutex<usize>,
+    idle: Condvar,
+    idle_async: Notify,
+    delivered: AtomicUsize,
+    dropped: AtomicUsize,
+    spooled: AtomicUsize,
+    /// The counts handed out by the previous report, so that each outcome is reported once.
+    reported: Mutex<FlushReport>,
+}
+
+impl Tracker {
+    pub fn begin(&self) {
+        *self.pending.lock().unwrap() += 1;
+    }
+
+    pub fn finish(&self, outcome: Outcome) {
+        self.count(outcome);
+        let mut pending = self.pending.lock().unwrap();
+        *pending = pending.saturating_sub(1);
+        if *pending == 0 {
+            drop(pending);
+            self.idle.notify_all();
+            self.idle_async.notify_waiters();
+        }
+    }
+
+    /// Records a notice that was turned away before it became pending.
+    pub fn count(&self, outcome: Outcome) {
+        let counter = match outcome {
+            Outcome::Delivered => &self.delivered,
+            Outcome::Dropped => &self.dropped,
+            Outcome::Spooled => &self.spooled,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn snapshot(&self) -> FlushReport {
+        FlushReport {
+            delivered: self.delivered.load(Ordering::SeqCst),
+            dropped: self.dropped.load(Ordering::SeqCst),
+            spooled: self.spooled.load(Ordering::SeqCst),
+            pending: *self.pending.lock().unwrap(),
+        }
+    }
+
+    pub async fn flush(&self, timeout: Duration) -> FlushReport {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let idle = self.idle_async.notified();
+            if *self.pending.lock().unwrap() == 0 {
+                break;
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                break;
+            }
+        }
+        self.report()
+    }
+
+    /// Waits on the calling thread, so the workers must be running elsewhere.
+    pub fn flush_blocking(&self, timeout: Duration) -> FlushReport {
+        let deadline = Instant::now() + timeout;
+        let mut pending = self.pending.lock().unwrap();
+        while *pending > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            pending = self.idle.wait_timeout(pending, remaining).unwrap().0;
+        }
+        drop(pending);
+        self.report()
+    }
+
+    fn report(&self) -> FlushReport {
+        let mut reported = self.reported.lock().unwrap();
+        let now = self.snapshot();
+        let report = FlushReport {
+            delivered: now.delivered - reported.delivered,
+            dropped: now.dropped - reported.dropped,
+            spooled: now.spooled - reported.spooled,
+            pending: now.pending,
+        };
+        *reported = now;
+        report
+    }
+}
+
+/// Flushes the notifier when dropped, e.g. at the end of `main`.
+///
+/// The flush blocks the dropping thread, so it only makes progress while the runtime
+/// running the delivery workers is still alive on other threads (a multi-threaded
+/// tokio runtime). Otherwise it simply gives up after the timeout.
+#[must_use = "the notifier is flushed when the guard is dropped"]
+#[derive(Debug)]
+pub struct FlushGuard {
+    notifier: Notifier,
+    timeout: Duration,
+}
+
+impl FlushGuard {
+    pub(crate) fn new(notifier: Notifier, timeout: Duration) -> Self {
+        Self { notifier, timeout }
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        self.notifier.flush_blocking(self.timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlushReport, Outcome, Tracker};
+    use crate::test_util::{created, notice, status, temp_dir, StubServer};
+    use crate::{Error, Notifier, QueueConfig, Result, RetryPolicy, SpoolConfig};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_flush() {
+        let tracker = Arc::new(Tracker::default());
+        tracker.count(Outcome::Delivered);
+        for _ in 0..3 {
+            tracker.begin();
+        }
+        let finisher = {
+            let tracker = tracker.clone();
+            tokio::spawn(async move {
+                for outcome in [Outcome::Delivered, Outcome::Spooled, Outcome::Dropped] {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    tracker.finish(outcome);
+                }
+            })
+        };
+        // the notice delivered before the flush is counted as well
+        let report = tracker.flush(Duration::from_secs(5)).await;
+        assert_eq!(
+            report,
+            FlushReport {
+                delivered: 2,
+                dropped: 1,
+                spooled: 1,
+                pending: 0,
+            }
+        );
+        finisher.await.unwrap();
+        assert_eq!(
+            tracker.flush(Duration::from_secs(5)).await,
+            FlushReport::default()
+        );
+    }
+
+    #[test]
+    fn test_flush_blocking_timeout() {
+        let tracker = Tracker::default();
+        tracker.begin();
+        let report = tracker.flush_blocking(Duration::from_millis(20));
+        assert_eq!(report.pending, 1);
+        tracker.finish(Outcome::Delivered);
+        assert_eq!(tracker.flush_blocking(Duration::from_secs(5)).pending, 0);
+    }
+
+    #[tokio::test]
+    async fn test_notifier_flush_and_close() -> Result<()> {
+        let spool_directory = temp_dir("flush");
+        let server = StubServer::start_with_delay(Duration::from_millis(20), |n| {
+            if n == 1 {
+                status(503)
+            } else if n == 2 {
+                status(400)
+            } else {
+                created(n)
+            }
+        })
+        .await;
+        let mut config = server.config();
+        config.retry = RetryPolicy::none();
+        config.queue = Some(QueueConfig::default());
+        config.spool = Some(SpoolConfig::new(&spool_directory));
+        let notifier = Notifier::new(config)?;
+        for i in 0..4 {
+            drop(notifier.try_enqueue(notice(&format!("notice {i}"))));
+        }
+        let report = notifier.flush(Duration::from_secs(5)).await;
+        assert_eq!(
+            report,
+            FlushReport {
+                delivered: 2,
+                dropped: 1,
+                spooled: 1,
+                pending: 0,
+            }
+        );
+        let report = notifier.close(Duration::from_secs(5)).await;
+        assert_eq!(report, FlushReport::default());
+        assert!(notifier.try_enqueue(notice("too late")).await.is_err());
+        let _ = std::fs::remove_dir_all(&spool_directory);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close_without_queue() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let clone = notifier.clone();
+        notifier.try_enqueue(notice("before")).await?;
+        assert_eq!(notifier.close(Duration::from_secs(5)).await.pending, 0);
+        for err in [
+            clone.try_enqueue(notice("too late")).await.unwrap_err(),
+            clone.notify_detached(notice("too late")).await.unwrap_err(),
+            clone.notify(notice("too late")).await.unwrap_err(),
+        ] {
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Dropped { .. })
+            ));
+        }
+        assert_eq!(server.request_count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_flush_guard() -> Result<()> {
+        let server = StubServer::start_with_delay(Duration::from_millis(50), created).await;
+        let mut config = server.config();
+        config.queue = Some(QueueConfig::default());
+        let notifier = Notifier::new(config)?;
+        {
+            let _guard = notifier.flush_guard(Duration::from_secs(5));
+            drop(notifier.try_enqueue(notice("first")));
+            drop(notifier.try_enqueue(notice("second")));
+        }
+        assert_eq!(server.request_count(), 2);
+        assert_eq!(notifier.flush(Duration::from_secs(0)).await.pending, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{Config, Error, Notifier, Result};
+use std::sync::OnceLock;
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+/// Creates the process-wide notifier used by `notify!` and `notify_message!`.
+///
+/// Like `Notifier::new`, it spawns the delivery workers onto the current tokio runtime
+/// when `config.queue` is set. Fails with `Error::AlreadyInitialized` when called twice.
+pub fn init(config: Config) -> Result<&'static Notifier> {
+    set_notifier(Notifier::new(config)?)
+}
+
+/// Makes an existing notifier the process-wide one.
+pub fn set_notifier(notifier: Notifier) -> Result<&'static Notifier> {
+    NOTIFIER
+        .set(notifier)
+        .map_err(|_| Error::AlreadyInitialized)?;
+    Ok(NOTIFIER.get().unwrap())
+}
+
+/// The process-wide notifier, if `init` or `set_notifier` was called.
+pub fn notifier() -> Option<&'static Notifier> {
+    NOTIFIER.get()
+}
+
+/// Used by the macros; not part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    use super::notifier;
+    use crate::{NoticeBuilder, NotifyHandle};
+    use serde_json::json;
+
+    /// Picks the `NoticeBuilder` constructor for `anyhow::Error` over the one for std
+    /// errors by method resolution, as the two cannot be told apart by a trait bound.
+    pub struct Wrap<'a, T: ?Sized>(pub &'a T);
+
+    pub trait AnyhowKind {
+        fn notice_builder(&self) -> NoticeBuilder;
+    }
+
+    impl AnyhowKind for Wrap<'_, anyhow::Error> {
+        fn notice_builder(&self) -> NoticeBuilder {
+            NoticeBuilder::from_anyhow_error(self.0)
+        }
+    }
+
+    impl AnyhowKind for Wrap<'_, &anyhow::Error> {
+        fn notice_builder(&self) -> NoticeBuilder {
+            NoticeBuilder::from_anyhow_error(self.0)
+        }
+    }
+
+    pub trait StdErrorKind {
+        fn notice_builder(&self) -> NoticeBuilder;
+    }
+
+    impl<E: std::error::Error + ?Sized> StdErrorKind for &Wrap<'_, E> {
+        fn notice_builder(&self) -> NoticeBuilder {
+            NoticeBuilder::from_std_error(self.0)
+        }
+    }
+
+    /// Builds the notice only when there is a notifier, and records the call site.
+    pub fn dispatch<F>(build: F, file: &str, line: u32, module: &str) -> Option<NotifyHandle>
+    where
+        F: FnOnce() -> NoticeBuilder,
+    {
+        let notifier = notifier()?;
+        let notice = build()
+            .param(
+                "reported_at",
+                json!({"file": file, "line": line, "module": module}),
+            )
+            .config(notifier.config())
+            .build();
+        Some(notifier.notify_detached(notice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init, notifier, set_notifier};
+    use crate::test_util::{created, StubServer};
+    use crate::{Config, Error, Notifier, Result, Severity};
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct Declined;
+
+    impl fmt::Display for Declined {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "card declined")
+        }
+    }
+
+    impl std::error::Error for Declined {}
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_global_notifier() -> Result<()> {
+        assert!(notifier().is_none());
+        assert!(crate::notify!(Declined).is_none());
+        assert!(crate::notify_message!("Check", "nobody listens").is_none());
+
+        let server = StubServer::start(created).await;
+        set_notifier(Notifier::new(server.config())?)?;
+        let err = init(Config::default()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::AlreadyInitialized)
+        ));
+
+        crate::notify!(Declined).unwrap().await?;
+        let error = anyhow::anyhow!("lost connection");
+        crate::notify!(error, severity = WARNING, component = "billing")
+            .unwrap()
+            .await?;
+        crate::notify!(&error, severity = Severity::CRITICAL,)
+            .unwrap()
+            .await?;
+        crate::notify_message!("UnexpectedState", format!("state {}", 3), severity = NOTICE)
+            .unwrap()
+            .await?;
+        let level = Severity::ALERT;
+        crate::notify!(Declined, severity = level).unwrap().await?;
+
+        let notices = server.notices()?;
+        assert_eq!(notices.len(), 5);
+        assert_eq!(notices[0].errors[0].message, "card declined");
+        assert_eq!(notices[0].context.severity, Some(Severity::ERROR));
+        let reported_at = &notices[0].params.as_ref().unwrap()["reported_at"];
+        assert_eq!(reported_at["file"], file!());
+        assert_eq!(reported_at["module"], module_path!());
+        assert!(reported_at["line"].as_u64().unwrap() > 0);
+        assert_eq!(notices[1].errors[0].message, "lost connection");
+        assert_eq!(notices[1].context.severity, Some(Severity::WARNING));
+        assert_eq!(notices[1].context.component.as_deref(), Some("billing"));
+        assert_eq!(notices[2].context.severity, Some(Severity::CRITICAL));
+        assert_eq!(notices[3].errors[0].type_, "UnexpectedState");
+        assert_eq!(notices[3].errors[0].message, "state 3");
+        assert_eq!(notices[3].context.severity, Some(Severity::NOTICE));
+        assert_eq!(notices[4].context.severity, Some(Severity::ALERT));
+        Ok(())
+    }
+}
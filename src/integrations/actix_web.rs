@@ -0,0 +1,269 @@
+use crate::{NoticeBuilder, Notifier, Redact, UserInfo};
+use ::actix_web::body::MessageBody;
+use ::actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use ::actix_web::http::{header, StatusCode};
+use ::actix_web::{Error, HttpRequest};
+use futures::future::LocalBoxFuture;
+use std::fmt;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+type UserExtractor = Arc<dyn Fn(&HttpRequest) -> Option<UserInfo> + Send + Sync>;
+
+/// An actix-web middleware that reports the responses with a status accepted by the
+/// status filter, 5xx by default, with the `actix_web::Error` they carry if any.
+///
+/// The context has the method, URI (redacted), match pattern, peer address and user
+/// agent of the request, and the user given by the user extractor.
+///
+/// ```no_run
+/// # fn run(notifier: errbit::Notifier) {
+/// use actix_web::App;
+/// use errbit::integrations::actix_web::Notify;
+///
+/// let app = App::new().wrap(
+///     Notify::new(notifier)
+///         .report_status(|status| status.is_server_error() || status.as_u16() == 429),
+/// );
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Notify {
+    notifier: Notifier,
+    redact: Arc<Redact>,
+    report_status: Arc<dyn Fn(StatusCode) -> bool + Send + Sync>,
+    user: Option<UserExtractor>,
+}
+
+impl Notify {
+    pub fn new(notifier: Notifier) -> Self {
+        Self {
+            notifier,
+            redact: Arc::new(Redact::new()),
+            report_status: Arc::new(|status| status.is_server_error()),
+            user: None,
+        }
+    }
+
+    /// Replaces the default `Redact` applied to the URI.
+    pub fn redact(mut self, redact: Redact) -> Self {
+        self.redact = Arc::new(redact);
+        self
+    }
+
+    /// Which response statuses are reported.
+    pub fn report_status<F>(mut self, report_status: F) -> Self
+    where
+        F: Fn(StatusCode) -> bool + Send + Sync + 'static,
+    {
+        self.report_status = Arc::new(report_status);
+        self
+    }
+
+    /// Tells the user a request was made by, before it is handled.
+    pub fn user<F>(mut self, user: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> Option<UserInfo> + Send + Sync + 'static,
+    {
+        self.user = Some(Arc::new(user));
+        self
+    }
+
+    fn request_info(&self, request: &HttpRequest) -> RequestInfo {
+        RequestInfo {
+            method: request.method().to_string(),
+            url: self.redact.redact_url(&request.uri().to_string()),
+            route: request.match_pattern(),
+            user_agent: request
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            remote_addr: request.peer_addr().map(|peer| peer.ip().to_string()),
+            user: self.user.as_ref().and_then(|user| user(request)),
+        }
+    }
+
+    fn report(&self, request: RequestInfo, status: StatusCode, error: Option<&Error>) {
+        if !(self.report_status)(status) {
+            return;
+        }
+        let builder = match error {
+            Some(error) => NoticeBuilder::from_std_error(error),
+            None => NoticeBuilder::message("ServerError", status.to_string()),
+        };
+        let mut builder = builder
+            .backtrace(Vec::new())
+            .http_method(request.method)
+            .url(request.url);
+        if let Some(route) = request.route {
+            builder = builder.route(route);
+        }
+        if let Some(user_agent) = request.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(remote_addr) = request.remote_addr {
+            builder = builder.remote_addr(remote_addr);
+        }
+        if let Some(user) = request.user {
+            builder = builder.user(user);
+        }
+        let notice = builder.config(self.notifier.config()).build();
+        self.notifier.notify_detached(notice);
+    }
+}
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notify")
+            .field("notifier", &self.notifier)
+            .field("redact", &self.redact)
+            .finish()
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Notify
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = NotifyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(NotifyMiddleware {
+            service,
+            notify: self.clone(),
+        }))
+    }
+}
+
+/// The service of `Notify`.
+#[derive(Debug)]
+pub struct NotifyMiddleware<S> {
+    service: S,
+    notify: Notify,
+}
+
+impl<S, B> Service<ServiceRequest> for NotifyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let info = self.notify.request_info(request.request());
+        let notify = self.notify.clone();
+        let future = self.service.call(request);
+        Box::pin(async move {
+            match future.await {
+                Ok(response) => {
+                    let status = response.status();
+                    notify.report(info, status, response.response().error());
+                    Ok(response)
+                }
+                Err(error) => {
+                    let status = error.as_response_error().status_code();
+                    notify.report(info, status, Some(&error));
+                    Err(error)
+                }
+            }
+        })
+    }
+}
+
+/// What is kept of a request for its notice, as the request itself cannot be kept
+/// while it is routed.
+struct RequestInfo {
+    method: String,
+    url: String,
+    route: Option<String>,
+    user_agent: Option<String>,
+    remote_addr: Option<String>,
+    user: Option<UserInfo>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Notify;
+    use crate::test_util::{created, StubServer};
+    use crate::{Notifier, Result, UserInfo};
+    use ::actix_web::{error, test, web, App, HttpResponse};
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_middleware() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let notify = Notify::new(notifier.clone()).user(|request| {
+            let id = request.headers().get("x-user-id")?.to_str().ok()?;
+            Some(UserInfo {
+                id: Some(id.to_owned()),
+                ..UserInfo::default()
+            })
+        });
+        let app = test::init_service(
+            App::new()
+                .wrap(notify)
+                .route(
+                    "/items/{id}",
+                    web::get().to(|| async { HttpResponse::ServiceUnavailable().finish() }),
+                )
+                .route(
+                    "/fail",
+                    web::post().to(|| async {
+                        Err::<HttpResponse, _>(error::ErrorInternalServerError("db down"))
+                    }),
+                )
+                .route(
+                    "/missing",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(error::ErrorNotFound("no such item"))
+                    }),
+                ),
+        )
+        .await;
+        for request in [
+            test::TestRequest::get()
+                .uri("/items/7?token=abc")
+                .peer_addr("192.0.2.7:5000".parse()?)
+                .insert_header(("user-agent", "curl/8.0"))
+                .insert_header(("x-user-id", "42")),
+            test::TestRequest::post().uri("/fail"),
+            test::TestRequest::get().uri("/missing"),
+        ] {
+            test::call_service(&app, request.to_request()).await;
+        }
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let notices = server.notices()?;
+        assert_eq!(notices.len(), 2);
+        let status = notices
+            .iter()
+            .find(|notice| notice.errors[0].type_ == "ServerError")
+            .unwrap();
+        assert_eq!(status.errors[0].message, "503 Service Unavailable");
+        let context = &status.context;
+        assert_eq!(context.http_method.as_deref(), Some("GET"));
+        assert_eq!(context.url.as_deref(), Some("/items/7?token=[Filtered]"));
+        assert_eq!(context.route.as_deref(), Some("/items/{id}"));
+        assert_eq!(context.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(context.remote_addr.as_deref(), Some("192.0.2.7"));
+        assert_eq!(context.user.as_ref().unwrap().id.as_deref(), Some("42"));
+        let error = notices
+            .iter()
+            .find(|notice| notice.errors[0].message == "db down")
+            .unwrap();
+        assert_eq!(error.context.http_method.as_deref(), Some("POST"));
+        assert!(error.context.user.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+pub use crate::integrations::tower::RequestScope;
+use crate::integrations::tower::{NotifyLayer, ResponseError};
+use crate::Notifier;
+use ::axum::async_trait;
+use ::axum::extract::{ConnectInfo, FromRequestParts, MatchedPath};
+use ::axum::http::request::Parts;
+use ::axum::http::StatusCode;
+use ::axum::response::{IntoResponse, Response};
+use std::net::SocketAddr;
+
+/// `NotifyLayer` reading the route from `MatchedPath` and the peer address from
+/// `ConnectInfo<SocketAddr>`.
+///
+/// Handlers can extract the `RequestScope` and, with `Extension<Notifier>`, the notifier.
+///
+/// ```no_run
+/// # async fn run(notifier: errbit::Notifier) {
+/// use axum::{routing::get, Router};
+/// use errbit::integrations::axum::{layer, HandlerError, RequestScope};
+/// use errbit::UserInfo;
+///
+/// async fn charge(scope: RequestScope) -> Result<&'static str, HandlerError> {
+///     scope.set_user(UserInfo {
+///         id: Some("7".to_owned()),
+///         ..UserInfo::default()
+///     });
+///     Err(anyhow::anyhow!("card declined"))?
+/// }
+///
+/// let app: Router = Router::new()
+///     .route("/charge", get(charge))
+///     .layer(layer(notifier));
+/// # }
+/// ```
+pub fn layer(notifier: Notifier) -> NotifyLayer {
+    NotifyLayer::new(notifier)
+        .route(|extensions| {
+            extensions
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_owned())
+        })
+        .peer_addr(|extensions| {
+            extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestScope {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<RequestScope>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "errbit NotifyLayer is not installed",
+        ))
+    }
+}
+
+/// A handler error responding with `500 Internal Server Error`, reported by the layer
+/// with the `anyhow::Error` it was made from.
+#[derive(Debug)]
+pub struct HandlerError(pub anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for HandlerError {
+    fn from(error: E) -> Self {
+        Self(error.into())
+    }
+}
+
+impl IntoResponse for HandlerError {
+    fn into_response(self) -> Response {
+        let mut response = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        response.extensions_mut().insert(ResponseError(self.0));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{layer, HandlerError, RequestScope};
+    use crate::test_util::{created, StubServer};
+    use crate::{Notifier, Result, UserInfo, FILTERED};
+    use ::axum::body::Body;
+    use ::axum::extract::{ConnectInfo, Extension, Path};
+    use ::axum::http::{Request, StatusCode};
+    use ::axum::routing::get;
+    use ::axum::Router;
+    use ::tower::ServiceExt;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    async fn charge(
+        Path(id): Path<u32>,
+        scope: RequestScope,
+        Extension(notifier): Extension<Notifier>,
+    ) -> std::result::Result<&'static str, HandlerError> {
+        assert!(!notifier.config().project_id.is_empty());
+        scope.set_user(UserInfo {
+            id: Some("7".to_owned()),
+            ..UserInfo::default()
+        });
+        scope.set_param("invoice_id", id);
+        scope.set_param("password", "hunter2");
+        scope.set_session("cart", vec![1, 2]);
+        scope.set_session("token", "abc");
+        Err(anyhow::anyhow!("card declined").context("charge failed"))?
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_router() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let app = Router::new()
+            .route("/charge/:id", get(charge))
+            .layer(layer(notifier.clone()));
+        let mut request = Request::get("/charge/42").body(Body::empty())?;
+        request
+            .extensions_mut()
+            .insert(ConnectInfo("192.0.2.7:5000".parse::<SocketAddr>()?));
+        let response = app.oneshot(request).await?;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let mut notices = server.notices()?;
+        assert_eq!(notices.len(), 1);
+        let notice = notices.remove(0);
+        assert_eq!(notice.errors[0].message, "charge failed");
+        assert_eq!(notice.errors[1].message, "card declined");
+        let context = &notice.context;
+        assert_eq!(context.route.as_deref(), Some("/charge/:id"));
+        assert_eq!(context.url.as_deref(), Some("/charge/42"));
+        assert_eq!(context.remote_addr.as_deref(), Some("192.0.2.7"));
+        assert_eq!(context.user.as_ref().unwrap().id.as_deref(), Some("7"));
+        let params = notice.params.unwrap();
+        assert_eq!(params["invoice_id"], 42);
+        assert_eq!(params["password"], FILTERED);
+        let session = notice.session.unwrap();
+        assert_eq!(session["cart"], serde_json::json!([1, 2]));
+        assert_eq!(session["token"], FILTERED);
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{client, BacktraceInfo, NoticeBuilder, Notifier, Severity};
+use ::log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// A `log::Log` that forwards every record to an inner logger and reports the records
+/// at or above a level, `Error` by default, as notices.
+///
+/// The record's target becomes the `component` and its file and line the only
+/// backtrace frame. Records of errbit itself, and the ones logged while it sends a
+/// notice, are not reported.
+///
+/// ```no_run
+/// # fn run(notifier: errbit::Notifier, inner: Box<dyn log::Log>) {
+/// use errbit::integrations::log::ErrbitLogger;
+///
+/// let logger = ErrbitLogger::new(inner, notifier).with_level(log::Level::Warn);
+/// log::set_boxed_logger(Box::new(logger)).unwrap();
+/// log::set_max_level(log::LevelFilter::Info);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ErrbitLogger<L> {
+    inner: L,
+    notifier: Notifier,
+    level: LevelFilter,
+}
+
+impl<L: Log> ErrbitLogger<L> {
+    pub fn new(inner: L, notifier: Notifier) -> Self {
+        Self {
+            inner,
+            notifier,
+            level: LevelFilter::Error,
+        }
+    }
+
+    /// Reports the records at `level` or more severe.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level.to_level_filter();
+        self
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    fn reports(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+            && !metadata.target().starts_with("errbit")
+            && !client::sending()
+    }
+
+    fn report(&self, record: &Record<'_>) {
+        let frame = BacktraceInfo {
+            file: record.file().map(str::to_owned),
+            function: record.module_path().map(str::to_owned),
+            line: record.line().map(|line| line as usize),
+            ..BacktraceInfo::default()
+        };
+        let notice = NoticeBuilder::message(record.level().as_str(), record.args().to_string())
+            .backtrace(vec![frame])
+            .severity(severity(record.level()))
+            .component(record.target())
+            .config(self.notifier.config())
+            .build();
+        self.notifier.notify_detached(notice);
+    }
+}
+
+impl<L: Log> Log for ErrbitLogger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.reports(metadata) || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.reports(record.metadata()) {
+            self.report(record);
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn severity(level: Level) -> Severity {
+    match level {
+        Level::Error => Severity::ERROR,
+        Level::Warn => Severity::WARNING,
+        Level::Info => Severity::INFO,
+        Level::Debug | Level::Trace => Severity::DEBUG,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrbitLogger;
+    use crate::test_util::{created, StubServer};
+    use crate::{client, Notifier, Result, Severity};
+    use ::log::{Level, Log, Metadata, Record};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Log for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_logger() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let logger =
+            ErrbitLogger::new(Recorder::default(), notifier.clone()).with_level(Level::Warn);
+        for (level, target, message) in [
+            (Level::Info, "billing", "charged"),
+            (Level::Warn, "billing", "card expires soon"),
+            (Level::Error, "errbit::client", "gateway down"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target(target)
+                    .args(format_args!("{message}"))
+                    .file(Some("src/billing.rs"))
+                    .line(Some(42))
+                    .module_path(Some("app::billing"))
+                    .build(),
+            );
+        }
+        assert_eq!(logger.inner().0.lock().unwrap().len(), 3);
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let mut notices = server.notices()?;
+        assert_eq!(notices.len(), 1);
+        let notice = notices.remove(0);
+        assert_eq!(notice.errors[0].type_, "WARN");
+        assert_eq!(notice.errors[0].message, "card expires soon");
+        assert_eq!(notice.context.severity, Some(Severity::WARNING));
+        assert_eq!(notice.context.component.as_deref(), Some("billing"));
+        let frames = notice.errors[0].backtrace.as_ref().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].line, Some(42));
+        assert_eq!(frames[0].function.as_deref(), Some("app::billing"));
+        assert!(frames[0].file.as_ref().unwrap().ends_with("src/billing.rs"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_logger_skips_records_while_sending() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let logger = ErrbitLogger::new(Recorder::default(), notifier.clone());
+        let record = || {
+            Record::builder()
+                .level(Level::Error)
+                .target("hyper::client")
+                .args(format_args!("connection error"))
+                .build()
+        };
+        client::SENDING
+            .scope((), async { logger.log(&record()) })
+            .await;
+        assert_eq!(logger.inner().0.lock().unwrap().len(), 1);
+        notifier.flush(Duration::from_secs(5)).await;
+        assert_eq!(server.request_count(), 0);
+        Ok(())
+    }
+}
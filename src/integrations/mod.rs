@@ -0,0 +1,38 @@
+//! Adapters reporting to a `Notifier` from other libraries, each behind the feature of
+//! the same name.
+
+#[cfg(feature = "actix-web")]
+pub mod actix_web;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "log")]
+pub mod log;
+#[cfg(feature = "tonic")]
+pub mod tonic;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+
+#[cfg(any(feature = "tower", feature = "tonic"))]
+use crate::Redact;
+#[cfg(any(feature = "tower", feature = "tonic"))]
+use serde_json::Value;
+#[cfg(any(feature = "tower", feature = "tonic"))]
+use std::collections::HashMap;
+
+/// The headers by name, the values of a repeated one joined with `, `, redacted.
+#[cfg(any(feature = "tower", feature = "tonic"))]
+pub(crate) fn header_values(headers: &http::HeaderMap, redact: &Redact) -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    for name in headers.keys() {
+        let values: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect();
+        map.insert(name.as_str().to_owned(), Value::String(values.join(", ")));
+    }
+    redact.redact_map(&mut map);
+    map
+}
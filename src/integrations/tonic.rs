@@ -0,0 +1,210 @@
+use super::header_values;
+use crate::{NoticeBuilder, Notifier, Redact};
+use ::tonic::{Code, Status};
+use futures::future::BoxFuture;
+use http::{HeaderMap, Request, Response, Uri};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// A `tower::Layer` for tonic servers that reports the RPCs answered with a `Status`
+/// whose code is in the reported set: `Internal`, `Unknown` and `DataLoss` by default.
+///
+/// The code becomes the error type and the status message the error message. The
+/// method path, such as `/billing.Payments/Charge`, is the `route` and `action` of
+/// the context, with `POST` as its method, and the request metadata goes into
+/// `params`, redacted. Only the status of the response head is seen, which is where
+/// tonic puts the status of a failed unary RPC, not one sent in the trailers of a
+/// stream.
+///
+/// ```no_run
+/// # fn run(notifier: errbit::Notifier) {
+/// use errbit::integrations::tonic::GrpcNotifyLayer;
+/// use tonic::Code;
+///
+/// let layer = GrpcNotifyLayer::new(notifier).codes(vec![
+///     Code::Internal,
+///     Code::Unknown,
+///     Code::DataLoss,
+///     Code::Unavailable,
+/// ]);
+/// // tonic::transport::Server::builder().layer(layer)
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct GrpcNotifyLayer {
+    notifier: Notifier,
+    redact: Arc<Redact>,
+    codes: Arc<Vec<Code>>,
+}
+
+impl GrpcNotifyLayer {
+    pub fn new(notifier: Notifier) -> Self {
+        Self {
+            notifier,
+            redact: Arc::new(Redact::new()),
+            codes: Arc::new(vec![Code::Internal, Code::Unknown, Code::DataLoss]),
+        }
+    }
+
+    /// Replaces the default `Redact` applied to the metadata.
+    pub fn redact(mut self, redact: Redact) -> Self {
+        self.redact = Arc::new(redact);
+        self
+    }
+
+    /// Replaces the codes of the statuses reported.
+    pub fn codes<I: IntoIterator<Item = Code>>(mut self, codes: I) -> Self {
+        self.codes = Arc::new(codes.into_iter().collect());
+        self
+    }
+
+    fn request_info<B>(&self, request: &Request<B>) -> RequestInfo {
+        RequestInfo {
+            uri: request.uri().clone(),
+            headers: request.headers().clone(),
+        }
+    }
+
+    /// Turns the request into notice data, redacted, only if the status is reported.
+    fn report(&self, request: RequestInfo, status: &Status) {
+        if !self.codes.contains(&status.code()) {
+            return;
+        }
+        let path = request.uri.path();
+        let mut builder = NoticeBuilder::message(format!("{:?}", status.code()), status.message())
+            .backtrace(Vec::new())
+            .http_method("POST")
+            .route(path)
+            .action(path);
+        if let Some(user_agent) = request
+            .headers
+            .get(http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+        {
+            builder = builder.user_agent(user_agent);
+        }
+        for (key, value) in header_values(&request.headers, &self.redact) {
+            builder = builder.param(key, value);
+        }
+        let notice = builder.config(self.notifier.config()).build();
+        self.notifier.notify_detached(notice);
+    }
+}
+
+impl<S> Layer<S> for GrpcNotifyLayer {
+    type Service = GrpcNotifyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcNotifyService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service of `GrpcNotifyLayer`.
+#[derive(Clone, Debug)]
+pub struct GrpcNotifyService<S> {
+    inner: S,
+    layer: GrpcNotifyLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcNotifyService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let info = self.layer.request_info(&request);
+        let future = self.inner.call(request);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let response = future.await?;
+            if let Some(status) = Status::from_header_map(response.headers()) {
+                layer.report(info, &status);
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// What is kept of a request in case its RPC fails.
+struct RequestInfo {
+    uri: Uri,
+    headers: HeaderMap,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GrpcNotifyLayer;
+    use crate::test_util::{created, StubServer};
+    use crate::{Notifier, Result, FILTERED};
+    use ::tonic::{Code, Status};
+    use ::tower::{service_fn, ServiceExt};
+    use http::{Request, Response};
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tower_layer::Layer;
+
+    fn request(path: &str) -> Request<()> {
+        Request::post(path)
+            .header("content-type", "application/grpc")
+            .header("user-agent", "grpc-go/1.58")
+            .header("authorization", "Bearer secret")
+            .header("x-request-id", "r-1")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_layer() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let layer =
+            GrpcNotifyLayer::new(notifier.clone()).codes(vec![Code::Internal, Code::Unavailable]);
+        let service = layer.layer(service_fn(|request: Request<()>| async move {
+            let response = match request.uri().path() {
+                "/billing.Payments/Charge" => Status::internal("db down").to_http(),
+                "/billing.Payments/Refund" => Status::not_found("no such charge").to_http(),
+                // not in the reported codes once they are replaced
+                "/billing.Payments/List" => Status::unknown("stream reset").to_http(),
+                _ => return Ok(Response::new(())),
+            };
+            Ok::<_, Infallible>(response.map(|_| ()))
+        }));
+        for path in [
+            "/billing.Payments/Charge",
+            "/billing.Payments/Refund",
+            "/billing.Payments/List",
+            "/billing.Payments/Get",
+        ] {
+            service.clone().oneshot(request(path)).await?;
+        }
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let mut notices = server.notices()?;
+        assert_eq!(notices.len(), 1);
+        let notice = notices.remove(0);
+        assert_eq!(notice.errors[0].type_, "Internal");
+        assert_eq!(notice.errors[0].message, "db down");
+        let context = &notice.context;
+        assert_eq!(context.http_method.as_deref(), Some("POST"));
+        assert_eq!(context.route.as_deref(), Some("/billing.Payments/Charge"));
+        assert_eq!(context.action.as_deref(), Some("/billing.Payments/Charge"));
+        assert_eq!(context.user_agent.as_deref(), Some("grpc-go/1.58"));
+        let params = notice.params.unwrap();
+        assert_eq!(params["authorization"], FILTERED);
+        assert_eq!(params["x-request-id"], "r-1");
+        Ok(())
+    }
+}
@@ -0,0 +1,501 @@
+use super::header_values;
+use crate::panic::panic_message;
+use crate::value::to_value;
+use crate::{NoticeBuilder, Notifier, Redact, Severity, UserInfo};
+use futures::future::{BoxFuture, FutureExt};
+use http::{Extensions, HeaderMap, Method, Request, Response, Uri};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+type Extract<T> = Arc<dyn Fn(&Extensions) -> Option<T> + Send + Sync>;
+
+/// A `tower::Layer` that reports the requests its service fails: with an `Err`, a 5xx
+/// response or a panic, which is resumed after reporting.
+///
+/// The notice has the method, URI, user agent and route of the request in its context,
+/// its headers in `environment` and its query parameters in `params`, both redacted.
+/// The `remote_addr` is the peer the request was received from, read from a
+/// `SocketAddr` request extension by default, and the `user_addr` the client behind
+/// the trusted proxies in `X-Forwarded-For`. What handlers add to the `RequestScope`
+/// is included, redacted as well, and an error in a `ResponseError` extension of the response is
+/// reported whatever the status.
+///
+/// The `Notifier` and the `RequestScope` are inserted into the request extensions.
+///
+/// ```no_run
+/// # fn run<S>(notifier: errbit::Notifier, service: S) {
+/// use errbit::integrations::tower::NotifyLayer;
+/// use tower_layer::Layer;
+///
+/// let layer = NotifyLayer::new(notifier).trusted_proxies(vec!["10.0.0.1".parse().unwrap()]);
+/// let service = layer.layer(service);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct NotifyLayer {
+    notifier: Notifier,
+    redact: Arc<Redact>,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+    route: Extract<String>,
+    peer_addr: Extract<IpAddr>,
+}
+
+impl NotifyLayer {
+    pub fn new(notifier: Notifier) -> Self {
+        Self {
+            notifier,
+            redact: Arc::new(Redact::new()),
+            trusted_proxies: Arc::new(Vec::new()),
+            route: Arc::new(|_| None),
+            peer_addr: Arc::new(|extensions| extensions.get::<SocketAddr>().map(|addr| addr.ip())),
+        }
+    }
+
+    /// Replaces the default `Redact` applied to the headers, query and URI.
+    pub fn redact(mut self, redact: Redact) -> Self {
+        self.redact = Arc::new(redact);
+        self
+    }
+
+    /// The proxies whose `X-Forwarded-For` entries are believed.
+    pub fn trusted_proxies<I: IntoIterator<Item = IpAddr>>(mut self, proxies: I) -> Self {
+        self.trusted_proxies = Arc::new(proxies.into_iter().collect());
+        self
+    }
+
+    /// Reads the matched route from the request extensions, as set by a router.
+    pub fn route<F>(mut self, route: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<String> + Send + Sync + 'static,
+    {
+        self.route = Arc::new(route);
+        self
+    }
+
+    /// Reads the address of the peer from the request extensions.
+    pub fn peer_addr<F>(mut self, peer_addr: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.peer_addr = Arc::new(peer_addr);
+        self
+    }
+
+    /// Keeps what `report` needs of the request. The extensions have to be read here,
+    /// before the request is handed to the inner service.
+    fn request_info<B>(&self, request: &Request<B>, scope: RequestScope) -> RequestInfo {
+        RequestInfo {
+            method: request.method().clone(),
+            uri: request.uri().clone(),
+            headers: request.headers().clone(),
+            route: (self.route)(request.extensions()),
+            remote_addr: (self.peer_addr)(request.extensions()),
+            scope,
+        }
+    }
+
+    /// Walks `X-Forwarded-For` from the peer back while the hops are trusted proxies.
+    fn client_addr(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in forwarded.into_iter().rev() {
+            if !self.trusted_proxies.contains(&client) {
+                break;
+            }
+            match hop.trim().parse() {
+                Ok(addr) => client = addr,
+                Err(_) => break,
+            }
+        }
+        client
+    }
+
+    /// Turns the request into notice data, redacted, only now that it failed.
+    fn report(&self, request: RequestInfo, builder: NoticeBuilder) {
+        let headers = &request.headers;
+        let mut builder = builder
+            .http_method(request.method.as_str())
+            .url(self.redact.redact_url(&request.uri.to_string()));
+        if let Some(route) = request.route {
+            builder = builder.route(route);
+        }
+        if let Some(user_agent) = headers
+            .get(http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+        {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(remote_addr) = request.remote_addr {
+            let user_addr = self.client_addr(remote_addr, headers);
+            builder = builder
+                .remote_addr(remote_addr.to_string())
+                .user_addr(user_addr.to_string());
+        }
+        for (key, value) in header_values(headers, &self.redact) {
+            builder = builder.env(key, value);
+        }
+        let mut params = request.uri.query().map(query_params).unwrap_or_default();
+        self.redact.redact_map(&mut params);
+        for (key, value) in params {
+            builder = builder.param(key, value);
+        }
+        builder = request.scope.apply(builder, &self.redact);
+        let notice = builder.config(self.notifier.config()).build();
+        self.notifier.notify_detached(notice);
+    }
+
+    fn report_panic(&self, request: RequestInfo, payload: &(dyn std::any::Any + Send)) {
+        let builder =
+            without_backtrace("panic", panic_message(payload)).severity(Severity::CRITICAL);
+        self.report(request, builder);
+    }
+}
+
+impl fmt::Debug for NotifyLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyLayer")
+            .field("notifier", &self.notifier)
+            .field("redact", &self.redact)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .finish()
+    }
+}
+
+impl<S> Layer<S> for NotifyLayer {
+    type Service = NotifyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        NotifyService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service of `NotifyLayer`.
+#[derive(Clone, Debug)]
+pub struct NotifyService<S> {
+    inner: S,
+    layer: NotifyLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for NotifyService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: fmt::Display + Send + 'static,
+    S::Future: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        let scope = RequestScope::default();
+        request.extensions_mut().insert(scope.clone());
+        request.extensions_mut().insert(self.layer.notifier.clone());
+        let info = self.layer.request_info(&request, scope);
+        let inner = &mut self.inner;
+        let future = match panic::catch_unwind(AssertUnwindSafe(|| inner.call(request))) {
+            Ok(future) => future,
+            Err(payload) => {
+                self.layer.report_panic(info, payload.as_ref());
+                panic::resume_unwind(payload);
+            }
+        };
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(Ok(mut response)) => {
+                    let status = response.status();
+                    if let Some(ResponseError(error)) = response.extensions_mut().remove() {
+                        layer.report(info, NoticeBuilder::from_anyhow_error(&error));
+                    } else if status.is_server_error() {
+                        layer.report(info, without_backtrace("ServerError", status.to_string()));
+                    }
+                    Ok(response)
+                }
+                Ok(Err(error)) => {
+                    let builder =
+                        without_backtrace(std::any::type_name::<S::Error>(), error.to_string());
+                    layer.report(info, builder);
+                    Err(error)
+                }
+                Err(payload) => {
+                    layer.report_panic(info, payload.as_ref());
+                    panic::resume_unwind(payload)
+                }
+            }
+        })
+    }
+}
+
+/// Notice data that handlers add while serving a request, reported if it fails.
+#[derive(Clone, Debug, Default)]
+pub struct RequestScope(Arc<Mutex<ScopeData>>);
+
+#[derive(Debug, Default)]
+struct ScopeData {
+    user: Option<UserInfo>,
+    params: HashMap<String, Value>,
+    session: HashMap<String, Value>,
+}
+
+impl RequestScope {
+    pub fn set_user(&self, user: UserInfo) {
+        self.0.lock().unwrap().user = Some(user);
+    }
+
+    /// Sets `params[key]`, over a query parameter of the same name.
+    pub fn set_param<K: Into<String>, T: Serialize>(&self, key: K, value: T) {
+        self.0
+            .lock()
+            .unwrap()
+            .params
+            .insert(key.into(), to_value(value));
+    }
+
+    /// Sets `session[key]`.
+    pub fn set_session<K: Into<String>, T: Serialize>(&self, key: K, value: T) {
+        self.0
+            .lock()
+            .unwrap()
+            .session
+            .insert(key.into(), to_value(value));
+    }
+
+    /// Adds the scope to the notice, with its params and session redacted like the
+    /// rest of the request.
+    fn apply(&self, mut builder: NoticeBuilder, redact: &Redact) -> NoticeBuilder {
+        let data = self.0.lock().unwrap();
+        if let Some(user) = &data.user {
+            builder = builder.user(user.clone());
+        }
+        let mut params = data.params.clone();
+        redact.redact_map(&mut params);
+        for (key, value) in params {
+            builder = builder.param(key, value);
+        }
+        let mut session = data.session.clone();
+        redact.redact_map(&mut session);
+        for (key, value) in session {
+            builder = builder.session(key, value);
+        }
+        builder
+    }
+}
+
+/// An error attached to a response, reported by `NotifyLayer` instead of the status.
+#[derive(Debug)]
+pub struct ResponseError(pub anyhow::Error);
+
+/// A notice for a failure that has no useful backtrace in the middleware.
+fn without_backtrace<T: Into<String>, M: Into<String>>(type_: T, message: M) -> NoticeBuilder {
+    NoticeBuilder::message(type_, message).backtrace(Vec::new())
+}
+
+/// What is kept of a request in case it fails.
+struct RequestInfo {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    route: Option<String>,
+    remote_addr: Option<IpAddr>,
+    scope: RequestScope,
+}
+
+/// The decoded query parameters; a repeated key has an array of its values.
+fn query_params(query: &str) -> HashMap<String, Value> {
+    let mut params: HashMap<String, Value> = HashMap::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = Value::String(percent_decode(value));
+        match params.get_mut(&percent_decode(key)) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None => {
+                params.insert(percent_decode(key), value);
+            }
+        }
+    }
+    params
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if bytes.len() > index + 2
+                && bytes[index + 1].is_ascii_hexdigit()
+                && bytes[index + 2].is_ascii_hexdigit() =>
+            {
+                let hex = &text[index + 1..index + 3];
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                index += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{query_params, NotifyLayer};
+    use crate::test_util::{created, StubServer};
+    use crate::{Notifier, Result, Severity, FILTERED};
+    use ::tower::{service_fn, ServiceExt};
+    use futures::FutureExt;
+    use http::{Request, Response, StatusCode};
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use std::panic::AssertUnwindSafe;
+    use std::time::Duration;
+    use tower_layer::Layer;
+
+    fn request(uri: &str) -> Request<()> {
+        let mut request = Request::post(uri)
+            .header("user-agent", "curl/8.0")
+            .header("authorization", "Bearer secret")
+            .header("x-forwarded-for", "203.0.113.9, 10.0.0.2")
+            .body(())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert("10.0.0.1:4000".parse::<SocketAddr>().unwrap());
+        request.extensions_mut().insert("/charge/:id");
+        request
+    }
+
+    fn layer(notifier: &Notifier) -> NotifyLayer {
+        NotifyLayer::new(notifier.clone())
+            .trusted_proxies(vec![
+                "10.0.0.1".parse().unwrap(),
+                "10.0.0.2".parse().unwrap(),
+            ])
+            .route(|extensions| extensions.get::<&str>().map(|route| route.to_string()))
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_server_error() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let service = layer(&notifier).layer(service_fn(|request: Request<()>| async move {
+            let status = match request.uri().path() {
+                "/charge/7" => StatusCode::BAD_GATEWAY,
+                _ => StatusCode::OK,
+            };
+            Ok::<_, std::fmt::Error>(Response::builder().status(status).body(()).unwrap())
+        }));
+        let response = service.clone().oneshot(request("/health")).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = service
+            .oneshot(request("/charge/7?token=abc&tag=a&tag=b%20c"))
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let mut notices = server.notices()?;
+        assert_eq!(notices.len(), 1);
+        let notice = notices.remove(0);
+        assert_eq!(notice.errors[0].type_, "ServerError");
+        assert_eq!(notice.errors[0].message, "502 Bad Gateway");
+        let context = &notice.context;
+        assert_eq!(context.http_method.as_deref(), Some("POST"));
+        assert_eq!(
+            context.url.as_deref(),
+            Some(format!("/charge/7?token={FILTERED}&tag=a&tag=b%20c").as_str())
+        );
+        assert_eq!(context.route.as_deref(), Some("/charge/:id"));
+        assert_eq!(context.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(context.remote_addr.as_deref(), Some("10.0.0.1"));
+        assert_eq!(context.user_addr.as_deref(), Some("203.0.113.9"));
+        let environment = notice.environment.unwrap();
+        assert_eq!(environment["authorization"], FILTERED);
+        assert_eq!(environment["user-agent"], "curl/8.0");
+        let params = notice.params.unwrap();
+        assert_eq!(params["token"], FILTERED);
+        assert_eq!(params["tag"], json!(["a", "b c"]));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_error_and_panic() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let service = layer(&notifier).layer(service_fn(|request: Request<()>| async move {
+            match request.uri().path() {
+                "/panic" => panic!("handler blew up"),
+                _ => Err::<Response<()>, _>(std::fmt::Error),
+            }
+        }));
+        assert!(service.clone().oneshot(request("/error")).await.is_err());
+        let panicked = AssertUnwindSafe(service.oneshot(request("/panic")))
+            .catch_unwind()
+            .await;
+        assert!(panicked.is_err());
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let notices = server.notices()?;
+        assert_eq!(notices.len(), 2);
+        let error = notices
+            .iter()
+            .find(|notice| notice.errors[0].type_ == "core::fmt::Error")
+            .unwrap();
+        assert_eq!(error.context.url.as_deref(), Some("/error"));
+        let panic = notices
+            .iter()
+            .find(|notice| notice.errors[0].type_ == "panic")
+            .unwrap();
+        assert_eq!(panic.errors[0].message, "handler blew up");
+        assert_eq!(panic.context.severity, Some(Severity::CRITICAL));
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_addr() {
+        let layer = NotifyLayer::new(Notifier::new(Default::default()).unwrap())
+            .trusted_proxies(vec!["10.0.0.1".parse().unwrap()]);
+        let request = request("/");
+        let headers = request.headers();
+        // 10.0.0.2 is not trusted, so it is as far as the chain can be followed
+        assert_eq!(
+            layer.client_addr("10.0.0.1".parse().unwrap(), headers),
+            "10.0.0.2".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(
+            layer.client_addr("192.0.2.1".parse().unwrap(), headers),
+            "192.0.2.1".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_query_params() {
+        let params = query_params("a=1&b=x+y&b=%2F&c&=&d=%zz");
+        assert_eq!(params["a"], "1");
+        assert_eq!(params["b"], json!(["x y", "/"]));
+        assert_eq!(params["c"], "");
+        assert_eq!(params["d"], "%zz");
+    }
+}
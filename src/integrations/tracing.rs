@@ -0,0 +1,369 @@
+use crate::{client, BacktraceInfo, NoticeBuilder, Notifier, Severity, UserInfo};
+use ::tracing::field::{Field, Visit};
+use ::tracing::span::{Attributes, Id, Record};
+use ::tracing::{Event, Level, Metadata, Subscriber};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use tracing_error::{ExtractSpanTrace, SpanTrace};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// A `tracing_subscriber::Layer` that reports the events at or above a level, `ERROR`
+/// by default, as notices.
+///
+/// The event's fields become `params`, its `message` the error message and its target
+/// the `component`. The spans it happened in are listed in `params.spans`, root first,
+/// and their fields fill the context: `http.method`, `http.url`, `http.route`,
+/// `http.user_agent`, `user.id`, `user.name` and `user.email`, the innermost span
+/// winning. The event's file and line are the first backtrace frame, followed by the
+/// `tracing_error::SpanTrace` of a recorded error or else by the spans of the event,
+/// innermost first. Events of errbit itself, and the ones emitted while it sends a
+/// notice, are not reported.
+///
+/// ```no_run
+/// # fn run(notifier: errbit::Notifier) {
+/// use errbit::integrations::tracing::ErrbitLayer;
+/// use tracing_subscriber::prelude::*;
+///
+/// tracing_subscriber::registry()
+///     .with(tracing_error::ErrorLayer::default())
+///     .with(ErrbitLayer::new(notifier))
+///     .init();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ErrbitLayer {
+    notifier: Notifier,
+    level: Level,
+}
+
+impl ErrbitLayer {
+    pub fn new(notifier: Notifier) -> Self {
+        Self {
+            notifier,
+            level: Level::ERROR,
+        }
+    }
+
+    /// Reports the events at `level` or more severe.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+/// The fields recorded for a span so far.
+struct SpanFields(Map<String, Value>);
+
+impl<S> Layer<S> for ErrbitLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut visitor = FieldVisitor::default();
+            attrs.record(&mut visitor);
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+                let mut visitor = FieldVisitor::default();
+                values.record(&mut visitor);
+                fields.extend(visitor.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if *metadata.level() > self.level
+            || metadata.target().starts_with("errbit")
+            || client::sending()
+        {
+            return;
+        }
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let message = match visitor.fields.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(message) => message.to_string(),
+            None => metadata.name().to_owned(),
+        };
+
+        let mut spans = Vec::new();
+        let mut span_frames = Vec::new();
+        let mut span_fields = Map::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let fields = extensions
+                    .get::<SpanFields>()
+                    .map(|SpanFields(fields)| fields.clone())
+                    .unwrap_or_default();
+                span_fields.extend(fields.clone());
+                spans.push(json!({
+                    "name": span.name(),
+                    "target": span.metadata().target(),
+                    "fields": fields,
+                }));
+                span_frames.insert(0, span_frame(span.metadata()));
+            }
+        }
+
+        let mut frames = vec![BacktraceInfo {
+            file: metadata.file().map(str::to_owned),
+            function: metadata.module_path().map(str::to_owned),
+            line: metadata.line().map(|line| line as usize),
+            ..BacktraceInfo::default()
+        }];
+        frames.extend(visitor.span_trace.unwrap_or(span_frames));
+
+        let mut builder = NoticeBuilder::message(metadata.level().as_str(), message)
+            .backtrace(frames)
+            .severity(severity(metadata.level()))
+            .component(metadata.target());
+        builder = with_span_context(builder, &span_fields);
+        if !spans.is_empty() {
+            builder = builder.param("spans", spans);
+        }
+        for (key, value) in visitor.fields {
+            builder = builder.param(key, value);
+        }
+        let notice = builder.config(self.notifier.config()).build();
+        self.notifier.notify_detached(notice);
+    }
+}
+
+/// Fills the context from the well-known span fields.
+fn with_span_context(mut builder: NoticeBuilder, fields: &Map<String, Value>) -> NoticeBuilder {
+    let field = |key: &str| {
+        fields.get(key).map(|value| match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        })
+    };
+    if let Some(method) = field("http.method") {
+        builder = builder.http_method(method);
+    }
+    if let Some(url) = field("http.url") {
+        builder = builder.url(url);
+    }
+    if let Some(route) = field("http.route") {
+        builder = builder.route(route);
+    }
+    if let Some(user_agent) = field("http.user_agent") {
+        builder = builder.user_agent(user_agent);
+    }
+    let user = UserInfo {
+        id: field("user.id"),
+        name: field("user.name"),
+        email: field("user.email"),
+    };
+    if user.id.is_some() || user.name.is_some() || user.email.is_some() {
+        builder = builder.user(user);
+    }
+    builder
+}
+
+fn span_trace_frames(span_trace: &SpanTrace) -> Vec<BacktraceInfo> {
+    let mut frames = Vec::new();
+    span_trace.with_spans(|metadata, _fields| {
+        frames.push(span_frame(metadata));
+        true
+    });
+    frames
+}
+
+/// A span as a frame, named `target::span`.
+fn span_frame(metadata: &Metadata<'_>) -> BacktraceInfo {
+    BacktraceInfo {
+        file: metadata.file().map(str::to_owned),
+        function: Some(format!("{}::{}", metadata.target(), metadata.name())),
+        line: metadata.line().map(|line| line as usize),
+        ..BacktraceInfo::default()
+    }
+}
+
+fn severity(level: &Level) -> Severity {
+    match *level {
+        Level::ERROR => Severity::ERROR,
+        Level::WARN => Severity::WARNING,
+        Level::INFO => Severity::INFO,
+        _ => Severity::DEBUG,
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    fields: Map<String, Value>,
+    /// The frames of the first recorded error with a `SpanTrace` in its chain.
+    span_trace: Option<Vec<BacktraceInfo>>,
+}
+
+impl FieldVisitor {
+    fn insert<T: Into<Value>>(&mut self, field: &Field, value: T) {
+        self.fields.insert(field.name().to_owned(), value.into());
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        if self.span_trace.is_none() {
+            self.span_trace = std::iter::successors(Some(value), |error| error.source())
+                .find_map(|error| error.span_trace())
+                .map(span_trace_frames);
+        }
+        self.insert(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{value:?}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrbitLayer;
+    use crate::test_util::{created, StubServer};
+    use crate::{Notifier, Result, Severity};
+    use std::time::Duration;
+    use tracing::Level;
+    use tracing_error::{ErrorLayer, InstrumentError};
+    use tracing_subscriber::prelude::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_layer() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let subscriber = tracing_subscriber::registry()
+            .with(ErrorLayer::default())
+            .with(ErrbitLayer::new(notifier.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!(
+                "request",
+                http.method = "POST",
+                http.route = "/charge",
+                user.id = tracing::field::Empty,
+            );
+            let _request = request.enter();
+            request.record("user.id", 7);
+            let charge = tracing::info_span!("charge", amount = 10);
+            let _charge = charge.enter();
+            tracing::warn!(target: "billing", "retrying");
+            tracing::error!(target: "billing", invoice_id = 42, "charge failed: {}", "declined");
+            tracing::error!(target: "errbit::client", "not reported");
+        });
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let mut notices = server.notices()?;
+        assert_eq!(notices.len(), 1);
+        let notice = notices.remove(0);
+        let error = &notice.errors[0];
+        assert_eq!(error.type_, "ERROR");
+        assert_eq!(error.message, "charge failed: declined");
+        let context = &notice.context;
+        assert_eq!(context.severity, Some(Severity::ERROR));
+        assert_eq!(context.component.as_deref(), Some("billing"));
+        assert_eq!(context.http_method.as_deref(), Some("POST"));
+        assert_eq!(context.route.as_deref(), Some("/charge"));
+        assert_eq!(context.user.as_ref().unwrap().id.as_deref(), Some("7"));
+        let params = notice.params.unwrap();
+        assert_eq!(params["invoice_id"], 42);
+        assert_eq!(params["spans"][0]["name"], "request");
+        assert_eq!(params["spans"][1]["fields"]["amount"], 10);
+        let frames = error.backtrace.as_ref().unwrap();
+        assert!(frames[0].file.as_ref().unwrap().ends_with("tracing.rs"));
+        let functions: Vec<_> = frames[1..]
+            .iter()
+            .map(|frame| frame.function.clone().unwrap())
+            .collect();
+        assert_eq!(
+            functions,
+            vec![
+                format!("{}::charge", module_path!()),
+                format!("{}::request", module_path!()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_layer_span_trace_of_error() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let subscriber = tracing_subscriber::registry()
+            .with(ErrorLayer::default())
+            .with(ErrbitLayer::new(notifier.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let error =
+                tracing::info_span!("lookup").in_scope(|| std::fmt::Error.in_current_span());
+            tracing::error!(
+                target: "billing",
+                error = &error as &(dyn std::error::Error + 'static),
+                "lookup failed"
+            );
+        });
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let notice = server.notices()?.remove(0);
+        let error = &notice.errors[0];
+        assert_eq!(error.message, "lookup failed");
+        let frames = error.backtrace.as_ref().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            frames[1].function,
+            Some(format!("{}::lookup", module_path!()))
+        );
+        assert_eq!(notice.params.unwrap()["error"], std::fmt::Error.to_string());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_layer_skips_events_while_sending() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        // hyper traces the delivery on the thread of the subscriber
+        let sender = notifier.clone();
+        std::thread::spawn(move || -> Result<()> {
+            let subscriber = tracing_subscriber::registry()
+                .with(ErrbitLayer::new(sender.clone()).with_level(Level::TRACE));
+            let _default = tracing::subscriber::set_default(subscriber);
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(async {
+                    tracing::error!(target: "billing", "charge failed");
+                    sender.flush(Duration::from_secs(5)).await;
+                });
+            Ok(())
+        })
+        .join()
+        .unwrap()?;
+        assert_eq!(server.request_count(), 1);
+        Ok(())
+    }
+}
@@ -1,19 +1,56 @@
+#![cfg_attr(feature = "nightly", feature(error_generic_member_access))]
+
+pub mod backtrace;
+mod builder;
 mod client;
 mod config;
 mod error;
+mod filter;
+mod flush;
+mod global;
+pub mod integrations;
+mod macros;
 mod notice;
 mod notifier;
+mod panic;
+mod queue;
+mod redact;
+mod report;
+mod retry;
+mod scope;
+mod source;
+mod spool;
+#[cfg(test)]
+mod test_util;
+mod value;
 
+pub use builder::NoticeBuilder;
 pub use client::Client;
 pub use config::Config;
 pub use error::{Error, Result};
+pub use filter::{Filter, IgnoreEnvironments, IgnoreErrorTypes, MinimumSeverity};
+pub use flush::{FlushGuard, FlushReport};
+#[doc(hidden)]
+pub use global::__private;
+pub use global::{init, notifier, set_notifier};
 pub use notice::*;
 pub use notifier::Notifier;
+pub use panic::install_panic_hook;
+pub use queue::{BatchConfig, NotifyHandle, OverflowPolicy, QueueConfig};
+pub use redact::{Redact, DEFAULT_SENSITIVE_KEYS, FILTERED};
+pub use report::{AnyhowResultExt, ResultExt};
+pub use retry::RetryPolicy;
+pub use scope::{with_scope, with_scope_sync, Scope};
+pub use source::SourceContextConfig;
+pub use spool::SpoolConfig;
+pub use value::{ValueLimits, TRUNCATED};
 
 #[cfg(test)]
 mod tests {
-    use crate::{Config, Notice, Notifier, Result};
+    use crate::test_util::{created, temp_dir, StubServer};
+    use crate::{Config, Error, Notice, Notifier, Result, Severity, SpoolConfig};
     use anyhow::Context;
+    use std::time::Duration;
 
     #[tokio::test]
     #[serial_test::serial]
@@ -43,7 +80,7 @@ mod tests {
             number_str
                 .parse::<i32>()
                 .map(|n| 2 * n)
-                .with_context(|| format!("Failed to parse number_str of {}", number_str))
+                .with_context(|| format!("Failed to parse number_str of {number_str}"))
         };
         let err = double_number("NOT A NUMBER").err().unwrap();
         let result = notifier.notify_anyhow_error(&err).await;
@@ -63,7 +100,7 @@ mod tests {
             number_str
                 .parse::<i32>()
                 .map(|n| 2 * n)
-                .with_context(|| format!("Failed to parse number_str of {}", number_str))
+                .with_context(|| format!("Failed to parse number_str of {number_str}"))
         };
         let err = double_number("NOT A NUMBER").err().unwrap();
         let mut notice = Notice::new_from_anyhow_error(&err, &config);
@@ -74,4 +111,99 @@ mod tests {
         assert!(!result.id.is_empty());
         Ok(())
     }
+
+    #[test]
+    fn test_notify_blocking() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let server = runtime.block_on(StubServer::start_with_delay(
+            Duration::from_millis(300),
+            created,
+        ));
+        let config = server.config();
+        let notifier = runtime.block_on(async { Notifier::new(config.clone()) })?;
+        let notice = Notice::new_from_anyhow_error(&anyhow::anyhow!("blocking"), &config);
+        let result = notifier.notify_blocking(notice.clone(), Duration::from_secs(5))?;
+        assert_eq!(result.id, "1");
+        let err = notifier
+            .notify_blocking(notice, Duration::from_millis(50))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Timeout { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_notify_blocking_timeout() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let server = runtime.block_on(StubServer::start_with_delay(
+            Duration::from_millis(200),
+            created,
+        ));
+        let spool_directory = temp_dir("blocking");
+        let mut config = server.config();
+        config.spool = Some(SpoolConfig::new(&spool_directory));
+        let notifier = runtime.block_on(async { Notifier::new(config.clone()) })?;
+        let notice = Notice::new_from_anyhow_error(&anyhow::anyhow!("slow"), &config);
+        let err = notifier
+            .notify_blocking(notice, Duration::from_millis(20))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Timeout { .. })
+        ));
+        // the send goes on and is waited for, and a local timeout is not spooled
+        let report = notifier.flush_blocking(Duration::from_secs(5));
+        assert_eq!((report.delivered, report.pending), (1, 0));
+        assert_eq!(server.request_count(), 1);
+        assert_eq!(std::fs::read_dir(&spool_directory)?.count(), 0);
+        std::fs::remove_dir_all(&spool_directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_notify_detached() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let server = runtime.block_on(StubServer::start(created));
+        let config = server.config();
+        let notifier = runtime.block_on(async { Notifier::new(config.clone()) })?;
+        let notice = Notice::new_from_anyhow_error(&anyhow::anyhow!("detached"), &config);
+        // no runtime on this thread, so a helper thread sends it
+        let handle = notifier.notify_detached(notice);
+        let report = notifier.flush_blocking(Duration::from_secs(5));
+        assert_eq!((report.delivered, report.pending), (1, 0));
+        assert_eq!(runtime.block_on(handle)?.id, "1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notify_message() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let result = notifier
+            .notify_message(
+                "UnexpectedState",
+                "payment gateway returned unexpected state",
+                Severity::WARNING,
+            )
+            .await?;
+        assert_eq!(result.id, "1");
+        notifier
+            .enqueue_message("UnexpectedState", "again", Severity::NOTICE)
+            .await
+            .await?;
+        let notices = server.notices()?;
+        let error = &notices[0].errors[0];
+        assert_eq!(error.type_, "UnexpectedState");
+        assert_eq!(error.message, "payment gateway returned unexpected state");
+        // only captured with `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` set
+        match crate::backtrace::capture_if_enabled() {
+            Some(_) => assert!(!error.backtrace.as_ref().unwrap().is_empty()),
+            None => assert!(error.backtrace.is_none()),
+        }
+        assert_eq!(notices[0].context.severity, Some(Severity::WARNING));
+        assert_eq!(notices[1].context.severity, Some(Severity::NOTICE));
+        Ok(())
+    }
 }
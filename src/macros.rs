@@ -0,0 +1,100 @@
+/// Reports an error, a std error or an `anyhow::Error`, through the global notifier.
+///
+/// Options are `NoticeBuilder` methods given as `name = value`; `severity` also takes
+/// the bare name of a `Severity` variant, besides any expression. The call site is recorded in `params.reported_at`.
+/// Evaluates to `Some(NotifyHandle)`, or to `None` without doing anything when
+/// `errbit::init` has not been called.
+///
+/// ```no_run
+/// # fn run(error: anyhow::Error) {
+/// errbit::notify!(error, severity = WARNING, component = "billing");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! notify {
+    ($error:expr $(, $($options:tt)*)?) => {
+        $crate::__private::dispatch(
+            || {
+                #[allow(unused_imports)]
+                use $crate::__private::{AnyhowKind as _, StdErrorKind as _};
+                let builder = (&$crate::__private::Wrap(&$error)).notice_builder();
+                $crate::__notice_options!(builder; $($($options)*)?)
+            },
+            file!(),
+            line!(),
+            module_path!(),
+        )
+    };
+}
+
+/// Reports a message that is not backed by an error value through the global notifier,
+/// with the backtrace of the call site if backtraces are enabled. Takes the same options
+/// as `notify!`.
+///
+/// ```no_run
+/// errbit::notify_message!("UnexpectedState", "payment gateway returned pending", severity = WARNING);
+/// ```
+#[macro_export]
+macro_rules! notify_message {
+    ($type_:expr, $message:expr $(, $($options:tt)*)?) => {
+        $crate::__private::dispatch(
+            || {
+                let builder = $crate::NoticeBuilder::from_message($type_, $message);
+                $crate::__notice_options!(builder; $($($options)*)?)
+            },
+            file!(),
+            line!(),
+            module_path!(),
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __notice_options {
+    ($builder:expr;) => {
+        $builder
+    };
+    ($builder:expr; severity = $severity:ident $(, $($rest:tt)*)?) => {
+        $crate::__notice_options!($builder.severity($crate::__severity!($severity)); $($($rest)*)?)
+    };
+    ($builder:expr; $option:ident = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::__notice_options!($builder.$option($value); $($($rest)*)?)
+    };
+}
+
+/// The `Severity` variant of that name, or else the value of the variable.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __severity {
+    (DEBUG) => {
+        $crate::Severity::DEBUG
+    };
+    (INFO) => {
+        $crate::Severity::INFO
+    };
+    (NOTICE) => {
+        $crate::Severity::NOTICE
+    };
+    (WARNING) => {
+        $crate::Severity::WARNING
+    };
+    (ERROR) => {
+        $crate::Severity::ERROR
+    };
+    (CRITICAL) => {
+        $crate::Severity::CRITICAL
+    };
+    (ALERT) => {
+        $crate::Severity::ALERT
+    };
+    (EMERGENCY) => {
+        $crate::Severity::EMERGENCY
+    };
+    (INVALID) => {
+        $crate::Severity::INVALID
+    };
+    ($severity:ident) => {
+        $severity
+    };
+}
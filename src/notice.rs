@@ -1,29 +1,59 @@
-use crate::Config;
-use serde::{Deserialize, Serialize, Serializer};
-use serde_json;
+use crate::{backtrace, Config, NoticeBuilder};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{self, Value};
 use std::collections::HashMap;
 use std::fmt;
 
 /// @see https://airbrake.io/docs/api/#create-notice-v3
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Notice {
     pub errors: Vec<ErrorInfo>,
     pub context: Context,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub environment: Option<HashMap<String, String>>,
+    pub environment: Option<HashMap<String, Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub session: Option<HashMap<String, String>>,
+    pub session: Option<HashMap<String, Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub params: Option<HashMap<String, String>>,
+    pub params: Option<HashMap<String, Value>>,
 }
 
 impl Notice {
-    pub fn new_from_std_error<E: std::error::Error>(error: &E, config: &Config) -> Self {
-        let error_info = ErrorInfo::new_with_error(error);
-        let mut context = Context::new_from_config(&config);
+    /// std errors rarely carry a backtrace, so if backtraces are enabled (see
+    /// `backtrace::capture_if_enabled`) the outermost entry gets the frames leading to
+    /// this call instead. With the `nightly` feature, the causes get the backtrace they
+    /// provide through `Error::provide`, if any.
+    pub fn new_from_std_error<E: std::error::Error + ?Sized>(error: &E, config: &Config) -> Self {
+        let errors = ErrorInfo::chain_from_std_error(error, config.max_error_chain_depth);
+        // only the sources are `'static`, so they can be asked for a backtrace they captured
+        #[cfg(feature = "nightly")]
+        let errors = {
+            let mut errors = errors;
+            attach_provided_backtraces(&mut errors[1..], error.source());
+            errors
+        };
+        Self::new_from_std_chain(errors, config)
+    }
+
+    /// Like `new_from_std_error`, but the error itself is asked for a backtrace as well,
+    /// which the error must be `'static` for.
+    #[cfg(feature = "nightly")]
+    pub fn new_from_provided_error(
+        error: &(dyn std::error::Error + 'static),
+        config: &Config,
+    ) -> Self {
+        let mut errors = ErrorInfo::chain_from_std_error(error, config.max_error_chain_depth);
+        attach_provided_backtraces(&mut errors, Some(error));
+        Self::new_from_std_chain(errors, config)
+    }
+
+    fn new_from_std_chain(mut errors: Vec<ErrorInfo>, config: &Config) -> Self {
+        if errors[0].backtrace.is_none() {
+            errors[0].backtrace = backtrace::capture_if_enabled();
+        }
+        let mut context = Context::new_from_config(config);
         context.severity = Some(Severity::ERROR);
         Self {
-            errors: vec![error_info],
+            errors,
             context,
             environment: None,
             session: None,
@@ -31,23 +61,73 @@ impl Notice {
         }
     }
     pub fn new_from_anyhow_error(error: &anyhow::Error, config: &Config) -> Self {
-        let error_info = ErrorInfo::from(error);
-        let mut context = Context::new_from_config(&config);
+        let errors = ErrorInfo::chain_from_anyhow_error(error, config.max_error_chain_depth);
+        let mut context = Context::new_from_config(config);
         context.severity = Some(Severity::ERROR);
         Self {
-            errors: vec![error_info],
+            errors,
             context,
             environment: None,
             session: None,
             params: None,
         }
     }
+    /// A notice about a condition that is not backed by an error value, with the
+    /// backtrace of the call site if backtraces are enabled.
+    pub fn new_from_message<T: Into<String>, M: Into<String>>(
+        type_: T,
+        message: M,
+        severity: Severity,
+        config: &Config,
+    ) -> Self {
+        NoticeBuilder::from_message(type_, message)
+            .severity(severity)
+            .config(config)
+            .build()
+    }
+    /// Sets `params[key]` to any serializable value, e.g. a request body.
+    pub fn set_param<K: Into<String>, T: Serialize>(
+        &mut self,
+        key: K,
+        value: T,
+    ) -> serde_json::Result<()> {
+        insert_value(&mut self.params, key.into(), value)
+    }
+    /// Sets `session[key]` to any serializable value.
+    pub fn set_session<K: Into<String>, T: Serialize>(
+        &mut self,
+        key: K,
+        value: T,
+    ) -> serde_json::Result<()> {
+        insert_value(&mut self.session, key.into(), value)
+    }
+    /// Sets `environment[key]` to any serializable value.
+    pub fn set_environment<K: Into<String>, T: Serialize>(
+        &mut self,
+        key: K,
+        value: T,
+    ) -> serde_json::Result<()> {
+        insert_value(&mut self.environment, key.into(), value)
+    }
     pub fn to_json(&self) -> String {
         serde_json::to_string(&self).unwrap()
     }
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+fn insert_value<T: Serialize>(
+    map: &mut Option<HashMap<String, Value>>,
+    key: String,
+    value: T,
+) -> serde_json::Result<()> {
+    let value = serde_json::to_value(value)?;
+    map.get_or_insert_with(HashMap::new).insert(key, value);
+    Ok(())
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ErrorInfo {
     #[serde(rename = "type")]
     pub type_: String,
@@ -57,79 +137,95 @@ pub struct ErrorInfo {
 }
 
 impl ErrorInfo {
-    pub fn new_with_error<E: std::error::Error>(error: &E) -> Self {
-        let type_ = format!("{:?}", error)
-            .split_whitespace()
-            .next()
-            .unwrap()
-            .to_owned();
-        let message = format!("{}", error);
+    pub fn new_with_error<E: std::error::Error + ?Sized>(error: &E) -> Self {
+        let type_ = type_from_debug(&format!("{error:?}"));
+        let message = format!("{error}");
         Self {
             type_,
             message,
             backtrace: None,
         }
     }
-}
 
-impl From<&anyhow::Error> for ErrorInfo {
-    fn from(error: &anyhow::Error) -> Self {
-        let type_ = format!("{:?}", error.root_cause())
-            .split_whitespace()
-            .next()
-            .unwrap()
-            .to_owned();
-        let message = format!("{}", error);
-        let backtrace_string = format!("{}", error.backtrace());
-        let backtraces: Vec<&str> = backtrace_string
-            .split("\n")
-            .into_iter()
-            .filter(|s| !s.is_empty())
-            .map(|s| s.trim())
-            .collect();
-        let mut backtrace_infos = vec![];
-        let mut item = BacktraceInfo::default();
-        let mut backtrace_iter = backtraces.into_iter();
-        loop {
-            if let Some(t) = backtrace_iter.next() {
-                if t.starts_with("at ") {
-                    let position_part = &t[3..];
-                    let position_info: Vec<&str> = position_part.split(":").into_iter().collect();
-                    if position_info.len() > 0 {
-                        item.file = Some(position_info[0].to_owned())
-                    }
-                    if position_info.len() > 1 {
-                        if let Ok(l) = position_info[1].parse::<usize>() {
-                            item.line = Some(l)
-                        }
-                    }
-                    if position_info.len() > 2 {
-                        if let Ok(c) = position_info[2].parse::<usize>() {
-                            item.column = Some(c)
-                        }
-                    }
-                    backtrace_infos.push(item.clone());
-                    item = BacktraceInfo::default();
-                } else {
-                    item = BacktraceInfo::default();
-                    item.function = Some(t.to_owned())
-                }
-            } else {
-                if !item.is_empty() {
-                    backtrace_infos.push(item.clone())
-                }
+    /// One entry per link of the `source()` chain, outermost first, keeping at most
+    /// `max_depth` of them.
+    pub fn chain_from_std_error<E: std::error::Error + ?Sized>(
+        error: &E,
+        max_depth: usize,
+    ) -> Vec<Self> {
+        let mut errors = vec![Self::new_with_error(error)];
+        let mut source = error.source();
+        while let Some(cause) = source {
+            if errors.len() >= max_depth {
                 break;
             }
+            errors.push(Self::new_with_error(cause));
+            source = cause.source();
         }
+        errors
+    }
+
+    /// Like `chain_from_std_error`, with the backtrace of the error attached to the
+    /// outermost entry.
+    pub fn chain_from_anyhow_error(error: &anyhow::Error, max_depth: usize) -> Vec<Self> {
+        let mut errors: Vec<Self> = error
+            .chain()
+            .take(max_depth.max(1))
+            .map(Self::new_with_error)
+            .collect();
+        errors[0].backtrace = backtrace::from_std_backtrace(error.backtrace());
+        errors
+    }
+
+    /// Attaches frames, e.g. from `backtrace::from_backtrace` for a caller-supplied backtrace.
+    pub fn with_backtrace(mut self, backtrace: Vec<BacktraceInfo>) -> Self {
+        self.backtrace = Some(backtrace);
+        self
+    }
+}
+
+impl From<&anyhow::Error> for ErrorInfo {
+    fn from(error: &anyhow::Error) -> Self {
+        let type_ = type_from_debug(&format!("{:?}", error.root_cause()));
+        let message = format!("{error}");
         Self {
             type_,
             message,
-            backtrace: Some(backtrace_infos),
+            backtrace: backtrace::from_std_backtrace(error.backtrace()),
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+/// Gives each entry the backtrace its error, starting at `error`, provides.
+#[cfg(feature = "nightly")]
+fn attach_provided_backtraces(
+    errors: &mut [ErrorInfo],
+    mut error: Option<&(dyn std::error::Error + 'static)>,
+) {
+    for info in errors.iter_mut() {
+        let cause = match error {
+            Some(cause) => cause,
+            None => break,
+        };
+        info.backtrace = backtrace::from_std_error(cause);
+        error = cause.source();
+    }
+}
+
+/// The name the `Debug` output of an error starts with, such as `ParseIntError` or
+/// `HighLevel` of `HighLevel(LowLevel)`. Errors printed as a bare message, like those
+/// of `anyhow!`, have none and are called `Error`.
+fn type_from_debug(debug: &str) -> String {
+    let end = debug
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(debug.len());
+    match &debug[..end] {
+        "" => "Error".to_owned(),
+        type_ => type_.to_owned(),
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct BacktraceInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub file: Option<String>,
@@ -141,6 +237,10 @@ pub struct BacktraceInfo {
     pub column: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<HashMap<String, String>>,
+    /// Whether the frame belongs to the application rather than std or a dependency.
+    #[serde(rename = "inApp")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_app: Option<bool>,
 }
 
 impl BacktraceInfo {
@@ -153,27 +253,17 @@ impl BacktraceInfo {
     }
 }
 
-impl Default for BacktraceInfo {
-    fn default() -> Self {
-        Self {
-            file: None,
-            function: None,
-            line: None,
-            column: None,
-            code: None,
-        }
-    }
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Context {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notifier: Option<NotifierInfo>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub environment: Option<String>,
     #[serde(
+        default,
         skip_serializing_if = "Option::is_none",
-        serialize_with = "serialize_severity"
+        serialize_with = "serialize_severity",
+        deserialize_with = "deserialize_severity"
     )]
     pub severity: Option<Severity>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -235,31 +325,7 @@ impl Context {
     }
 }
 
-impl Default for Context {
-    fn default() -> Self {
-        Self {
-            notifier: None,
-            environment: None,
-            severity: None,
-            component: None,
-            action: None,
-            os: None,
-            hostname: None,
-            language: None,
-            version: None,
-            url: None,
-            user_agent: None,
-            user_addr: None,
-            remote_addr: None,
-            root_directory: None,
-            user: None,
-            route: None,
-            http_method: None,
-        }
-    }
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct UserInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
@@ -281,7 +347,27 @@ where
     serializer.serialize_str(severity.to_string().as_str())
 }
 
-#[derive(Debug, Serialize)]
+fn deserialize_severity<'de, D>(deserializer: D) -> Result<Option<Severity>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(
+        value.map(|severity| match severity.to_lowercase().as_str() {
+            "debug" => Severity::DEBUG,
+            "info" => Severity::INFO,
+            "notice" => Severity::NOTICE,
+            "warning" => Severity::WARNING,
+            "error" => Severity::ERROR,
+            "critical" => Severity::CRITICAL,
+            "alert" => Severity::ALERT,
+            "emergency" => Severity::EMERGENCY,
+            _ => Severity::INVALID,
+        }),
+    )
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NotifierInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
@@ -294,9 +380,9 @@ pub struct NotifierInfo {
 impl Default for NotifierInfo {
     fn default() -> Self {
         Self {
-            name: Some(format!("{}", env!("CARGO_PKG_NAME"))),
-            version: Some(format!("{}", env!("CARGO_PKG_VERSION"))),
-            url: Some(format!("{}", env!("CARGO_PKG_REPOSITORY"))),
+            name: Some(env!("CARGO_PKG_NAME").to_string()),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            url: Some(env!("CARGO_PKG_REPOSITORY").to_string()),
         }
     }
 }
@@ -340,6 +426,33 @@ pub struct NotifyResult {
 mod tests {
     use super::{Context as ErrorContext, ErrorInfo, Notice, NotifierInfo, Severity};
     use anyhow::{Context, Result};
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct LowLevel;
+
+    impl fmt::Display for LowLevel {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "connection reset")
+        }
+    }
+
+    impl std::error::Error for LowLevel {}
+
+    #[derive(Debug)]
+    struct HighLevel(LowLevel);
+
+    impl fmt::Display for HighLevel {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "failed to load user")
+        }
+    }
+
+    impl std::error::Error for HighLevel {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
 
     #[test]
     fn test_error_info_from_std_error() {
@@ -358,11 +471,17 @@ mod tests {
             number_str
                 .parse::<i32>()
                 .map(|n| 2 * n)
-                .with_context(|| format!("Failed to parse number_str of {}", number_str))
+                .with_context(|| format!("Failed to parse number_str of {number_str}"))
         };
         let err = double_number("NOT A NUMBER").err().unwrap();
         let error_info = ErrorInfo::from(&err);
-        assert!(error_info.backtrace.unwrap().len() > 0);
+        // anyhow only captures with `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` set
+        match err.backtrace().status() {
+            std::backtrace::BacktraceStatus::Captured => {
+                assert!(!error_info.backtrace.unwrap().is_empty())
+            }
+            _ => assert!(error_info.backtrace.is_none()),
+        }
     }
 
     #[test]
@@ -372,15 +491,17 @@ mod tests {
             message: "This is test".to_owned(),
             backtrace: None,
         };
-        let mut context = ErrorContext::default();
-        context.http_method = Some("GET".to_owned());
+        let context = ErrorContext {
+            http_method: Some("GET".to_owned()),
+            ..ErrorContext::default()
+        };
         let notice = Notice {
             errors: vec![error_info],
             context,
             environment: None,
             session: None,
             params: Some(
-                [("param1".to_owned(), "1".to_owned())]
+                [("param1".to_owned(), "1".into())]
                     .iter()
                     .cloned()
                     .collect(),
@@ -398,10 +519,12 @@ mod tests {
             message: "This is test".to_owned(),
             backtrace: None,
         };
-        let mut context = ErrorContext::default();
-        context.http_method = Some("POST".to_owned());
-        context.severity = Some(Severity::INFO);
-        context.notifier = Some(NotifierInfo::default());
+        let context = ErrorContext {
+            http_method: Some("POST".to_owned()),
+            severity: Some(Severity::INFO),
+            notifier: Some(NotifierInfo::default()),
+            ..ErrorContext::default()
+        };
         let notice = Notice {
             errors: vec![error_info],
             context,
@@ -413,4 +536,122 @@ mod tests {
         let expected = r##"{"errors":[{"type":"Error","message":"This is test"}],"context":{"notifier":{"name":"errbit","version":"0.1.0","url":"https://github.com/kumanote/errbit-rs"},"severity":"info","httpMethod":"POST"}}"##;
         assert_eq!(json, expected);
     }
+
+    #[test]
+    fn test_notice_round_trip() {
+        let err = anyhow::anyhow!("This is test");
+        let mut notice = Notice::new_from_anyhow_error(&err, &crate::Config::default());
+        notice.context.severity = Some(Severity::WARNING);
+        notice.session = Some([("user".to_owned(), "1".into())].iter().cloned().collect());
+        let json = notice.to_json();
+        let restored = Notice::from_json(&json).unwrap();
+        assert_eq!(restored.context.severity, Some(Severity::WARNING));
+        assert_eq!(restored.to_json(), json);
+    }
+
+    #[test]
+    fn test_set_values() {
+        #[derive(serde::Serialize)]
+        struct Body {
+            ids: Vec<u32>,
+            admin: bool,
+        }
+        let mut notice =
+            Notice::new_from_anyhow_error(&anyhow::anyhow!("x"), &crate::Config::default());
+        notice
+            .set_param(
+                "body",
+                Body {
+                    ids: vec![1, 2],
+                    admin: false,
+                },
+            )
+            .unwrap();
+        notice.set_session("user_id", 7).unwrap();
+        notice.set_environment("RUST_LOG", "debug").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&notice.to_json()).unwrap();
+        assert_eq!(json["params"]["body"]["ids"][1], 2);
+        assert_eq!(json["params"]["body"]["admin"], false);
+        assert_eq!(json["session"]["user_id"], 7);
+        assert_eq!(json["environment"]["RUST_LOG"], "debug");
+    }
+
+    #[test]
+    fn test_error_chain_from_std_error() {
+        let err = HighLevel(LowLevel);
+        let errors = ErrorInfo::chain_from_std_error(&err, 10);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].type_, "HighLevel");
+        assert_eq!(errors[0].message, "failed to load user");
+        assert_eq!(errors[1].type_, "LowLevel");
+        assert_eq!(errors[1].message, "connection reset");
+        assert_eq!(ErrorInfo::chain_from_std_error(&err, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_notice_from_std_error_has_backtrace() {
+        let notice = Notice::new_from_std_error(&HighLevel(LowLevel), &crate::Config::default());
+        // only captured with `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` set
+        match crate::backtrace::capture_if_enabled() {
+            Some(_) => {
+                let frames = notice.errors[0].backtrace.as_ref().unwrap();
+                assert!(frames.iter().any(|frame| frame.line.is_some()));
+            }
+            None => assert!(notice.errors[0].backtrace.is_none()),
+        }
+        assert!(notice.errors[1].backtrace.is_none());
+    }
+
+    #[cfg(feature = "nightly")]
+    #[test]
+    fn test_notice_from_std_error_provided_backtrace() {
+        #[derive(Debug)]
+        struct Provided(::backtrace::Backtrace);
+        impl std::fmt::Display for Provided {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "provided")
+            }
+        }
+        impl std::error::Error for Provided {
+            fn provide<'a>(&'a self, request: &mut std::error::Request<'a>) {
+                request.provide_ref(&self.0);
+            }
+        }
+        let error = Provided(::backtrace::Backtrace::new());
+        let notice = Notice::new_from_provided_error(&error, &crate::Config::default());
+        let frames = notice.errors[0].backtrace.as_ref().unwrap();
+        assert_eq!(
+            frames.len(),
+            crate::backtrace::from_backtrace(&error.0).len()
+        );
+    }
+
+    #[test]
+    fn test_error_chain_from_anyhow() {
+        let err = Err::<(), _>(HighLevel(LowLevel))
+            .context("request failed")
+            .unwrap_err();
+        let errors = ErrorInfo::chain_from_anyhow_error(&err, 10);
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["request failed", "failed to load user", "connection reset"]
+        );
+        assert_eq!(
+            errors[0].backtrace.is_some(),
+            err.backtrace().status() == std::backtrace::BacktraceStatus::Captured
+        );
+        assert!(errors[1].backtrace.is_none());
+        let errors = ErrorInfo::chain_from_anyhow_error(&err, 2);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].type_, "HighLevel");
+
+        // links that are only a message have no type of their own
+        let err = anyhow::anyhow!("lost connection to db").context("outer");
+        let types: Vec<String> = ErrorInfo::chain_from_anyhow_error(&err, 10)
+            .into_iter()
+            .map(|e| e.type_)
+            .collect();
+        assert_eq!(types, vec!["Error", "Error"]);
+    }
 }
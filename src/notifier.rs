@@ -1,28 +1,409 @@
-use crate::{Client, Config, Notice, NotifyResult, Result};
+use crate::backtrace;
+use crate::filter::Filters;
+use crate::flush::{FlushGuard, Outcome, Tracker};
+use crate::queue::Queue;
+use crate::scope;
+use crate::source::SourceCache;
+use crate::spool::Spool;
+use crate::{
+    Client, Config, Error, Filter, FlushReport, Notice, NotifyHandle, NotifyResult, Result,
+    Severity,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct Notifier {
     config: Config,
     client: Client,
+    queue: Option<Queue>,
+    spool: Option<Spool>,
+    source: Option<Arc<SourceCache>>,
+    filters: Arc<Filters>,
+    tracker: Arc<Tracker>,
+    closed: Arc<AtomicBool>,
 }
 
 impl Notifier {
+    /// Creates a notifier that sends inline, or spawns the delivery workers
+    /// onto the current tokio runtime when `config.queue` is set.
+    ///
+    /// With `config.spool` set, notices left over from earlier runs are resent
+    /// in the background if a tokio runtime is available.
     pub fn new(config: Config) -> Result<Self> {
-        let client = Client::new(config.endpoint().as_str())?;
-        Ok(Self { config, client })
+        let client =
+            Client::new(config.endpoint().as_str())?.with_retry_policy(config.retry.clone());
+        let spool = match &config.spool {
+            Some(spool_config) => Some(Spool::open(spool_config.clone())?),
+            None => None,
+        };
+        let source = match (&config.app_root_directory, &config.source_context) {
+            (Some(root), Some(source_config)) => {
+                Some(Arc::new(SourceCache::new(root, source_config.clone())))
+            }
+            _ => None,
+        };
+        let tracker = Arc::new(Tracker::default());
+        let queue = match &config.queue {
+            Some(queue_config) => Some(Queue::start(
+                client.clone(),
+                spool.clone(),
+                source.clone(),
+                tracker.clone(),
+                queue_config.clone(),
+            )?),
+            None => None,
+        };
+        if let Some(spool) = &spool {
+            spool.replay_in_background(&client);
+        }
+        Ok(Self {
+            config,
+            client,
+            queue,
+            spool,
+            source,
+            filters: Arc::new(Filters::default()),
+            tracker,
+            closed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Adds a filter that every notice passes through before it is sent, after the
+    /// ones added earlier. Clones of this notifier share their filters.
+    pub fn add_filter<F: Filter + 'static>(&self, filter: F) {
+        self.filters.add(Arc::new(filter));
     }
 
     pub async fn notify(&self, notice: Notice) -> Result<NotifyResult> {
-        self.client.notify(&notice).await
+        let notice = self.prepare(notice)?;
+        match &self.queue {
+            Some(queue) => queue.push(notice).await.await,
+            None => {
+                deliver(
+                    &self.client,
+                    self.spool.as_ref(),
+                    self.source.as_ref(),
+                    &notice,
+                )
+                .await
+                .0
+            }
+        }
     }
 
-    pub async fn notify_error<E: std::error::Error>(&self, error: &E) -> Result<NotifyResult> {
+    pub async fn notify_error<E: std::error::Error + ?Sized>(
+        &self,
+        error: &E,
+    ) -> Result<NotifyResult> {
         let notice = Notice::new_from_std_error(error, &self.config);
-        self.client.notify(&notice).await
+        self.notify(notice).await
     }
 
     pub async fn notify_anyhow_error(&self, error: &anyhow::Error) -> Result<NotifyResult> {
         let notice = Notice::new_from_anyhow_error(error, &self.config);
-        self.client.notify(&notice).await
+        self.notify(notice).await
+    }
+
+    /// Reports a condition that is not backed by an error value, e.g. an unexpected
+    /// response, with the backtrace of the call site if backtraces are enabled.
+    pub async fn notify_message<T: Into<String>, M: Into<String>>(
+        &self,
+        type_: T,
+        message: M,
+        severity: Severity,
+    ) -> Result<NotifyResult> {
+        let notice = Notice::new_from_message(type_, message, severity, &self.config);
+        self.notify(notice).await
+    }
+
+    /// Hands the notice over for background delivery, waiting for a free slot
+    /// only when the queue is full and its overflow policy is `Block`.
+    pub async fn enqueue(&self, notice: Notice) -> NotifyHandle {
+        let notice = match self.prepare(notice) {
+            Ok(notice) => notice,
+            Err(e) => return NotifyHandle::ready(Err(e.into())),
+        };
+        match &self.queue {
+            Some(queue) => queue.push(notice).await,
+            None => self.spawn(notice),
+        }
+    }
+
+    /// Like `enqueue` but never waits: a full `Block` queue rejects the notice.
+    pub fn try_enqueue(&self, notice: Notice) -> NotifyHandle {
+        let notice = match self.prepare(notice) {
+            Ok(notice) => notice,
+            Err(e) => return NotifyHandle::ready(Err(e.into())),
+        };
+        match &self.queue {
+            Some(queue) => queue.try_push(notice),
+            None => self.spawn(notice),
+        }
+    }
+
+    pub async fn enqueue_error<E: std::error::Error + ?Sized>(&self, error: &E) -> NotifyHandle {
+        let notice = Notice::new_from_std_error(error, &self.config);
+        self.enqueue(notice).await
+    }
+
+    pub async fn enqueue_anyhow_error(&self, error: &anyhow::Error) -> NotifyHandle {
+        let notice = Notice::new_from_anyhow_error(error, &self.config);
+        self.enqueue(notice).await
+    }
+
+    pub async fn enqueue_message<T: Into<String>, M: Into<String>>(
+        &self,
+        type_: T,
+        message: M,
+        severity: Severity,
+    ) -> NotifyHandle {
+        let notice = Notice::new_from_message(type_, message, severity, &self.config);
+        self.enqueue(notice).await
+    }
+
+    /// Like `try_enqueue`, but outside of a tokio runtime the notice is sent from a helper
+    /// thread instead of being rejected, so it can be used from any code.
+    pub fn notify_detached(&self, notice: Notice) -> NotifyHandle {
+        if self.queue.is_some() || tokio::runtime::Handle::try_current().is_ok() {
+            return self.try_enqueue(notice);
+        }
+        let notice = match self.prepare(notice) {
+            Ok(notice) => notice,
+            Err(e) => return NotifyHandle::ready(Err(e.into())),
+        };
+        let (sender, handle) = NotifyHandle::channel();
+        let client = self.client.with_fresh_connections();
+        let spool = self.spool.clone();
+        let source = self.source.clone();
+        let tracker = self.tracker.clone();
+        tracker.begin();
+        let spawned = {
+            let tracker = tracker.clone();
+            thread::Builder::new()
+                .name("errbit-notify".to_owned())
+                .spawn(move || {
+                    let (result, outcome) = match tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                    {
+                        Ok(runtime) => runtime.block_on(deliver(
+                            &client,
+                            spool.as_ref(),
+                            source.as_ref(),
+                            &notice,
+                        )),
+                        Err(e) => (Err(e.into()), Outcome::Dropped),
+                    };
+                    tracker.finish(outcome);
+                    let _ = sender.send(result);
+                })
+        };
+        match spawned {
+            Ok(_) => handle,
+            Err(e) => {
+                tracker.finish(Outcome::Dropped);
+                NotifyHandle::ready(Err(e.into()))
+            }
+        }
+    }
+
+    /// Sends the notice from a helper thread running its own runtime and waits at most
+    /// `timeout`, so it works from synchronous code and from within a runtime alike.
+    ///
+    /// A send still running at the deadline goes on in the background, counted by `flush`,
+    /// and only that send spools the notice if it fails.
+    pub fn notify_blocking(&self, notice: Notice, timeout: Duration) -> Result<NotifyResult> {
+        let notice = self.prepare(notice)?;
+        let (sender, receiver) = mpsc::channel();
+        let client = self.client.with_fresh_connections();
+        let spool = self.spool.clone();
+        let source = self.source.clone();
+        let tracker = self.tracker.clone();
+        tracker.begin();
+        let spawned = {
+            let tracker = tracker.clone();
+            thread::Builder::new()
+                .name("errbit-notify".to_owned())
+                .spawn(move || {
+                    let (result, outcome) = match tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                    {
+                        Ok(runtime) => runtime.block_on(deliver(
+                            &client,
+                            spool.as_ref(),
+                            source.as_ref(),
+                            &notice,
+                        )),
+                        Err(e) => (Err(e.into()), Outcome::Dropped),
+                    };
+                    tracker.finish(outcome);
+                    let _ = sender.send(result);
+                })
+        };
+        match spawned {
+            Ok(_) => receiver
+                .recv_timeout(timeout)
+                .unwrap_or_else(|_| Err(Error::Timeout { timeout }.into())),
+            Err(e) => {
+                tracker.finish(Outcome::Dropped);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Resends the notices kept in the spool directory and returns how many were delivered,
+    /// none when a replay is already running in the background.
+    pub async fn replay_spool(&self) -> Result<usize> {
+        match &self.spool {
+            Some(spool) => spool.replay(&self.client).await,
+            None => Ok(0),
+        }
+    }
+
+    /// Waits up to `timeout` for the notices handed to `enqueue` and `try_enqueue`
+    /// to be settled. The report also counts those settled before the call, since the
+    /// previous flush.
+    pub async fn flush(&self, timeout: Duration) -> FlushReport {
+        self.tracker.flush(timeout).await
+    }
+
+    /// Stops accepting notices, in every mode and for all clones, then flushes the ones
+    /// already handed over. Notices sent afterwards fail with `Error::Dropped`.
+    pub async fn close(&self, timeout: Duration) -> FlushReport {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Some(queue) = &self.queue {
+            queue.close();
+        }
+        self.flush(timeout).await
+    }
+
+    /// Like `flush` but blocks the calling thread instead of awaiting.
+    pub fn flush_blocking(&self, timeout: Duration) -> FlushReport {
+        self.tracker.flush_blocking(timeout)
+    }
+
+    /// Returns a guard that flushes this notifier when dropped.
+    ///
+    /// ```no_run
+    /// # async fn run(notifier: errbit::Notifier) {
+    /// let _guard = notifier.flush_guard(std::time::Duration::from_secs(2));
+    /// # }
+    /// ```
+    pub fn flush_guard(&self, timeout: Duration) -> FlushGuard {
+        FlushGuard::new(self.clone(), timeout)
     }
+
+    /// Sends the notice, or only hands it over in queued mode, ignoring the outcome.
+    pub(crate) async fn report(&self, notice: Notice) {
+        match &self.queue {
+            Some(_) => {
+                self.enqueue(notice).await;
+            }
+            None => {
+                let _ = self.notify(notice).await;
+            }
+        }
+    }
+
+    /// Completes the notice, with the current scope merged in, and runs the filters,
+    /// which may drop it.
+    fn prepare(&self, mut notice: Notice) -> std::result::Result<Notice, Error> {
+        if self.closed.load(Ordering::SeqCst) {
+            self.tracker.count(Outcome::Dropped);
+            return Err(Error::Dropped {
+                reason: "notifier is closed".to_owned(),
+            });
+        }
+        scope::apply_current(&mut notice);
+        let backtraces = notice
+            .errors
+            .iter_mut()
+            .filter_map(|error| error.backtrace.as_mut());
+        for backtrace in backtraces {
+            backtrace::tidy(backtrace, &self.config);
+        }
+        for map in [
+            &mut notice.params,
+            &mut notice.session,
+            &mut notice.environment,
+        ]
+        .iter_mut()
+        .filter_map(|map| map.as_mut())
+        {
+            self.config.value_limits.truncate_map(map);
+        }
+        self.filters.apply(notice).ok_or(Error::Filtered)
+    }
+
+    fn spawn(&self, notice: Notice) -> NotifyHandle {
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let (sender, handle) = NotifyHandle::channel();
+                let client = self.client.clone();
+                let spool = self.spool.clone();
+                let source = self.source.clone();
+                let tracker = self.tracker.clone();
+                tracker.begin();
+                runtime.spawn(async move {
+                    let (result, outcome) =
+                        deliver(&client, spool.as_ref(), source.as_ref(), &notice).await;
+                    tracker.finish(outcome);
+                    let _ = sender.send(result);
+                });
+                handle
+            }
+            Err(e) => NotifyHandle::ready(Err(Error::Runtime {
+                reason: format!("{e}"),
+            }
+            .into())),
+        }
+    }
+}
+
+/// Attaches the source code context, if enabled, then sends the notice and keeps it in
+/// the spool, if any, when the endpoint is unreachable.
+pub(crate) async fn deliver(
+    client: &Client,
+    spool: Option<&Spool>,
+    source: Option<&Arc<SourceCache>>,
+    notice: &Notice,
+) -> (Result<NotifyResult>, Outcome) {
+    let annotated = match source {
+        Some(source) => source.annotated(notice).await,
+        None => None,
+    };
+    let notice = annotated.as_ref().unwrap_or(notice);
+    let paused = client.rate_limited_until().is_some();
+    let result = client.notify(notice).await;
+    let spooled = match spool {
+        // turned away during a rate limit pause without reaching the server: keeping
+        // every notice of a storm on disk would undo the pause
+        Some(_) if paused && is_rate_limited(&result) => false,
+        Some(spool) => spool.track(client, notice, &result).await,
+        None => false,
+    };
+    let outcome = match (&result, spooled) {
+        (Ok(_), _) => Outcome::Delivered,
+        (Err(_), true) => Outcome::Spooled,
+        (Err(_), false) => Outcome::Dropped,
+    };
+    (result, outcome)
+}
+
+fn is_rate_limited(result: &Result<NotifyResult>) -> bool {
+    matches!(
+        result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<Error>()),
+        Some(Error::RateLimited { .. })
+    )
 }
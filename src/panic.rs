@@ -0,0 +1,125 @@
+use crate::{backtrace, BacktraceInfo, Context, ErrorInfo, Notice, Notifier, Severity};
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::panic::{self, Location};
+use std::thread;
+use std::time::Duration;
+
+thread_local! {
+    static REPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Reports every panic as a `CRITICAL` notice before handing it on to the
+/// previously installed hook.
+///
+/// The notice is sent synchronously, waiting at most `timeout`, so that it goes out
+/// before the panicking thread unwinds or the process aborts.
+pub fn install_panic_hook(notifier: &Notifier, timeout: Duration) {
+    let notifier = notifier.clone();
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // a panic while reporting must not recurse into another report
+        if !REPORTING.with(|reporting| reporting.replace(true)) {
+            let notice = new_panic_notice(&notifier, info.payload(), info.location());
+            let _ = notifier.notify_blocking(notice, timeout);
+            REPORTING.with(|reporting| reporting.set(false));
+        }
+        previous(info);
+    }));
+}
+
+fn new_panic_notice(
+    notifier: &Notifier,
+    payload: &(dyn Any + Send),
+    location: Option<&Location<'_>>,
+) -> Notice {
+    let message = panic_message(payload);
+    let mut backtrace = vec![];
+    if let Some(location) = location {
+        backtrace.push(BacktraceInfo {
+            file: Some(location.file().to_owned()),
+            line: Some(location.line() as usize),
+            column: Some(location.column() as usize),
+            ..BacktraceInfo::default()
+        });
+    }
+    backtrace.extend(backtrace::capture());
+    let mut context = Context::new_from_config(notifier.config());
+    context.severity = Some(Severity::CRITICAL);
+    let thread = thread::current();
+    let mut params = HashMap::new();
+    params.insert(
+        "thread".to_owned(),
+        thread.name().unwrap_or("<unnamed>").into(),
+    );
+    Notice {
+        errors: vec![ErrorInfo {
+            type_: "panic".to_owned(),
+            message,
+            backtrace: Some(backtrace),
+        }],
+        context,
+        environment: None,
+        session: None,
+        params: Some(params),
+    }
+}
+
+/// The message a panic was raised with.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{install_panic_hook, new_panic_notice};
+    use crate::test_util::{created, StubServer};
+    use crate::{Notice, Notifier, Result, Severity};
+    use std::panic::Location;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_new_panic_notice() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let payload: Box<dyn std::any::Any + Send> = Box::new(String::from("boom"));
+        let notice = new_panic_notice(&notifier, payload.as_ref(), Some(Location::caller()));
+        assert_eq!(notice.context.severity, Some(Severity::CRITICAL));
+        let error = &notice.errors[0];
+        assert_eq!(error.type_, "panic");
+        assert_eq!(error.message, "boom");
+        let frame = &error.backtrace.as_ref().unwrap()[0];
+        assert_eq!(frame.file.as_deref(), Some(file!()));
+        assert!(notice.params.unwrap().contains_key("thread"));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[serial_test::serial]
+    async fn test_panic_hook() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        install_panic_hook(&notifier, Duration::from_secs(5));
+        let panicked = std::thread::Builder::new()
+            .name("doomed".to_owned())
+            .spawn(|| panic!("panic hook test"))?
+            .join();
+        let _ = std::panic::take_hook();
+        assert!(panicked.is_err());
+        let reported: Vec<Notice> = server
+            .notices()?
+            .into_iter()
+            .filter(|notice| notice.errors[0].message == "panic hook test")
+            .collect();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].params.as_ref().unwrap()["thread"], "doomed");
+        Ok(())
+    }
+}
@@ -0,0 +1,523 @@
+use crate::flush::{Outcome, Tracker};
+use crate::notifier::deliver;
+use crate::source::SourceCache;
+use crate::spool::Spool;
+use crate::{Client, Error, Notice, NotifyResult, Result};
+use futures::StreamExt;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{oneshot, Notify};
+
+/// What to do with an incoming notice when the queue is already full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Reject the incoming notice.
+    DropNewest,
+    /// Evict the oldest queued notice to make room for the incoming one.
+    DropOldest,
+    /// Wait until a worker frees a slot.
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub workers: usize,
+    pub overflow: OverflowPolicy,
+    /// Let each worker gather several notices and send them together.
+    pub batch: Option<BatchConfig>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            workers: 1,
+            overflow: OverflowPolicy::DropNewest,
+            batch: None,
+        }
+    }
+}
+
+/// A worker flushes once `max_size` notices are gathered or `max_wait` has passed
+/// since the first one, sending up to `concurrency` of them at the same time.
+///
+/// The requests share the client's connection pool, so against an `https` endpoint
+/// that negotiates HTTP/2 a whole batch is multiplexed over a single connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchConfig {
+    pub max_size: usize,
+    pub max_wait: Duration,
+    pub concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 50,
+            max_wait: Duration::from_millis(200),
+            concurrency: 8,
+        }
+    }
+}
+
+/// Resolves to the result of a queued notice once a worker has sent it.
+///
+/// Dropping the handle does not cancel the delivery.
+#[derive(Debug)]
+pub struct NotifyHandle {
+    receiver: oneshot::Receiver<Result<NotifyResult>>,
+}
+
+impl NotifyHandle {
+    pub(crate) fn channel() -> (oneshot::Sender<Result<NotifyResult>>, Self) {
+        let (sender, receiver) = oneshot::channel();
+        (sender, Self { receiver })
+    }
+
+    pub(crate) fn ready(result: Result<NotifyResult>) -> Self {
+        let (sender, handle) = Self::channel();
+        let _ = sender.send(result);
+        handle
+    }
+}
+
+impl Future for NotifyHandle {
+    type Output = Result<NotifyResult>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver)
+            .poll(cx)
+            .map(|received| match received {
+                Ok(result) => result,
+                Err(_) => Err(Error::Dropped {
+                    reason: "queue was shut down".to_owned(),
+                }
+                .into()),
+            })
+    }
+}
+
+struct Job {
+    notice: Notice,
+    sender: oneshot::Sender<Result<NotifyResult>>,
+}
+
+impl Job {
+    fn new(notice: Notice) -> (Self, NotifyHandle) {
+        let (sender, handle) = NotifyHandle::channel();
+        (Self { notice, sender }, handle)
+    }
+
+    fn reject(self, reason: &str) {
+        let _ = self.sender.send(Err(Error::Dropped {
+            reason: reason.to_owned(),
+        }
+        .into()));
+    }
+}
+
+struct State {
+    jobs: VecDeque<Job>,
+    closed: bool,
+}
+
+struct Shared {
+    config: QueueConfig,
+    state: Mutex<State>,
+    pushed: Notify,
+    popped: Notify,
+    tracker: Arc<Tracker>,
+}
+
+impl Shared {
+    fn new(config: QueueConfig, tracker: Arc<Tracker>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                jobs: VecDeque::with_capacity(config.capacity),
+                closed: false,
+            }),
+            config,
+            pushed: Notify::new(),
+            popped: Notify::new(),
+            tracker,
+        }
+    }
+
+    /// Hands the job back when the queue is full and the caller is willing to wait.
+    fn push(&self, job: Job, wait: bool) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            drop(state);
+            self.tracker.count(Outcome::Dropped);
+            job.reject("queue is closed");
+            return None;
+        }
+        if state.jobs.len() >= self.config.capacity.max(1) {
+            match self.config.overflow {
+                OverflowPolicy::DropOldest => {
+                    if let Some(oldest) = state.jobs.pop_front() {
+                        self.tracker.finish(Outcome::Dropped);
+                        oldest.reject("evicted by a newer notice");
+                    }
+                }
+                OverflowPolicy::Block if wait => return Some(job),
+                OverflowPolicy::DropNewest | OverflowPolicy::Block => {
+                    drop(state);
+                    self.tracker.count(Outcome::Dropped);
+                    job.reject("queue is full");
+                    return None;
+                }
+            }
+        }
+        self.tracker.begin();
+        state.jobs.push_back(job);
+        drop(state);
+        self.pushed.notify_one();
+        None
+    }
+
+    async fn push_wait(&self, mut job: Job) {
+        loop {
+            let popped = self.popped.notified();
+            match self.push(job, true) {
+                None => return,
+                Some(returned) => job = returned,
+            }
+            popped.await;
+        }
+    }
+
+    async fn pop(&self) -> Option<Job> {
+        loop {
+            let pushed = self.pushed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(job) = state.jobs.pop_front() {
+                    drop(state);
+                    self.popped.notify_one();
+                    return Some(job);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            pushed.await;
+        }
+    }
+
+    /// Waits for a first job, then keeps collecting until `max_size` jobs are
+    /// gathered or `max_wait` has passed. Empty once the queue is closed and drained.
+    async fn pop_batch(&self, max_size: usize, max_wait: Duration) -> Vec<Job> {
+        let mut jobs = match self.pop().await {
+            Some(job) => vec![job],
+            None => return vec![],
+        };
+        let deadline = tokio::time::Instant::now() + max_wait;
+        while jobs.len() < max_size {
+            match tokio::time::timeout_at(deadline, self.pop()).await {
+                Ok(Some(job)) => jobs.push(job),
+                _ => break,
+            }
+        }
+        jobs
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.pushed.notify_waiters();
+        self.popped.notify_waiters();
+    }
+}
+
+/// Closes the queue once the last `Notifier` clone sharing it is dropped,
+/// letting the workers drain what is left and exit.
+struct Producer {
+    shared: Arc<Shared>,
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Queue {
+    producer: Arc<Producer>,
+}
+
+impl Queue {
+    /// Spawns the workers onto the current tokio runtime.
+    pub fn start(
+        client: Client,
+        spool: Option<Spool>,
+        source: Option<Arc<SourceCache>>,
+        tracker: Arc<Tracker>,
+        config: QueueConfig,
+    ) -> Result<Self> {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|e| Error::Runtime {
+            reason: format!("{e}"),
+        })?;
+        let workers = config.workers.max(1);
+        let shared = Arc::new(Shared::new(config, tracker));
+        for _ in 0..workers {
+            runtime.spawn(work(
+                shared.clone(),
+                client.clone(),
+                spool.clone(),
+                source.clone(),
+            ));
+        }
+        Ok(Self {
+            producer: Arc::new(Producer { shared }),
+        })
+    }
+
+    pub async fn push(&self, notice: Notice) -> NotifyHandle {
+        let (job, handle) = Job::new(notice);
+        self.producer.shared.push_wait(job).await;
+        handle
+    }
+
+    pub fn try_push(&self, notice: Notice) -> NotifyHandle {
+        let (job, handle) = Job::new(notice);
+        self.producer.shared.push(job, false);
+        handle
+    }
+
+    /// Rejects new notices while the workers finish the queued ones.
+    pub fn close(&self) {
+        self.producer.shared.close();
+    }
+}
+
+impl fmt::Debug for Queue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queue")
+            .field("config", &self.producer.shared.config)
+            .finish()
+    }
+}
+
+async fn work(
+    shared: Arc<Shared>,
+    client: Client,
+    spool: Option<Spool>,
+    source: Option<Arc<SourceCache>>,
+) {
+    match shared.config.batch.clone() {
+        None => {
+            while let Some(job) = shared.pop().await {
+                send(
+                    job,
+                    &client,
+                    spool.as_ref(),
+                    source.as_ref(),
+                    &shared.tracker,
+                )
+                .await;
+            }
+        }
+        Some(batch) => loop {
+            let jobs = shared
+                .pop_batch(batch.max_size.max(1), batch.max_wait)
+                .await;
+            if jobs.is_empty() {
+                break;
+            }
+            futures::stream::iter(jobs)
+                .for_each_concurrent(batch.concurrency.max(1), |job| {
+                    send(
+                        job,
+                        &client,
+                        spool.as_ref(),
+                        source.as_ref(),
+                        &shared.tracker,
+                    )
+                })
+                .await;
+        },
+    }
+}
+
+async fn send(
+    job: Job,
+    client: &Client,
+    spool: Option<&Spool>,
+    source: Option<&Arc<SourceCache>>,
+    tracker: &Tracker,
+) {
+    let (result, outcome) = deliver(client, spool, source, &job.notice).await;
+    tracker.finish(outcome);
+    let _ = job.sender.send(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchConfig, Job, OverflowPolicy, QueueConfig, Shared};
+    use crate::test_util::{created, notice, StubServer};
+    use crate::{Error, Notifier, Result};
+    use std::time::Duration;
+
+    fn shared(capacity: usize, overflow: OverflowPolicy) -> Shared {
+        Shared::new(
+            QueueConfig {
+                capacity,
+                workers: 1,
+                overflow,
+                batch: None,
+            },
+            Default::default(),
+        )
+    }
+
+    fn is_dropped(result: Result<crate::NotifyResult>) -> bool {
+        matches!(
+            result.err().and_then(|e| e.downcast::<Error>().ok()),
+            Some(Error::Dropped { .. })
+        )
+    }
+
+    #[tokio::test]
+    async fn test_drop_newest() {
+        let shared = shared(1, OverflowPolicy::DropNewest);
+        let (first, _first_handle) = Job::new(notice("first"));
+        let (second, second_handle) = Job::new(notice("second"));
+        assert!(shared.push(first, false).is_none());
+        assert!(shared.push(second, false).is_none());
+        assert!(is_dropped(second_handle.await));
+        let job = shared.pop().await.unwrap();
+        assert_eq!(job.notice.errors[0].message, "first");
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let shared = shared(1, OverflowPolicy::DropOldest);
+        let (first, first_handle) = Job::new(notice("first"));
+        let (second, _second_handle) = Job::new(notice("second"));
+        shared.push(first, false);
+        shared.push(second, false);
+        assert!(is_dropped(first_handle.await));
+        let job = shared.pop().await.unwrap();
+        assert_eq!(job.notice.errors[0].message, "second");
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let shared = std::sync::Arc::new(shared(1, OverflowPolicy::Block));
+        let (first, _first_handle) = Job::new(notice("first"));
+        let (second, _second_handle) = Job::new(notice("second"));
+        shared.push(first, false);
+        let pusher = {
+            let shared = shared.clone();
+            tokio::spawn(async move { shared.push_wait(second).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!pusher.is_finished());
+        assert_eq!(
+            shared.pop().await.unwrap().notice.errors[0].message,
+            "first"
+        );
+        tokio::time::timeout(Duration::from_secs(1), pusher)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            shared.pop().await.unwrap().notice.errors[0].message,
+            "second"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closed_queue_drains_then_stops() {
+        let shared = shared(2, OverflowPolicy::DropNewest);
+        let (first, _first_handle) = Job::new(notice("first"));
+        let (second, second_handle) = Job::new(notice("second"));
+        shared.push(first, false);
+        shared.close();
+        shared.push(second, false);
+        assert!(is_dropped(second_handle.await));
+        assert!(shared.pop().await.is_some());
+        assert!(shared.pop().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_queued_notifier() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let mut config = server.config();
+        config.queue = Some(QueueConfig {
+            capacity: 10,
+            workers: 2,
+            overflow: OverflowPolicy::Block,
+            batch: None,
+        });
+        let notifier = Notifier::new(config)?;
+        let handles = vec![
+            notifier.enqueue(notice("first")).await,
+            notifier.try_enqueue(notice("second")),
+        ];
+        for handle in handles {
+            assert!(!handle.await?.id.is_empty());
+        }
+        let result = notifier.notify(notice("third")).await?;
+        assert!(!result.id.is_empty());
+        assert_eq!(server.request_count(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pop_batch() {
+        let shared = shared(10, OverflowPolicy::DropNewest);
+        let mut handles = vec![];
+        for i in 0..3 {
+            let (job, handle) = Job::new(notice(&format!("notice {i}")));
+            shared.push(job, false);
+            handles.push(handle);
+        }
+        let batch = shared.pop_batch(2, Duration::from_secs(10)).await;
+        assert_eq!(batch.len(), 2);
+        let batch = shared.pop_batch(2, Duration::from_millis(20)).await;
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].notice.errors[0].message, "notice 2");
+        shared.close();
+        assert!(shared
+            .pop_batch(2, Duration::from_secs(10))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batched_notifier() -> Result<()> {
+        let server = StubServer::start_with_delay(Duration::from_millis(100), created).await;
+        let mut config = server.config();
+        config.queue = Some(QueueConfig {
+            capacity: 100,
+            workers: 1,
+            overflow: OverflowPolicy::Block,
+            batch: Some(BatchConfig {
+                max_size: 5,
+                max_wait: Duration::from_millis(50),
+                concurrency: 5,
+            }),
+        });
+        let notifier = Notifier::new(config)?;
+        for round in 0..2 {
+            let mut handles = vec![];
+            for i in 0..5 {
+                handles.push(notifier.try_enqueue(notice(&format!("notice {round}-{i}"))));
+            }
+            let results = futures::future::join_all(handles).await;
+            assert!(results.iter().all(|result| result.is_ok()));
+        }
+        assert_eq!(server.request_count(), 10);
+        assert_eq!(server.max_in_flight(), 5);
+        assert!(server.connection_count() <= 5);
+        Ok(())
+    }
+}
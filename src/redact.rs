@@ -0,0 +1,306 @@
+use crate::{Filter, Notice, Result};
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// What the values of sensitive keys are replaced with.
+pub const FILTERED: &str = "[Filtered]";
+
+/// Keys blocked by `Redact::new`, matched anywhere in a key regardless of case.
+pub const DEFAULT_SENSITIVE_KEYS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "authorization",
+    "api_key",
+    "apikey",
+    "access_key",
+    "private_key",
+    "credit_card",
+    "card_number",
+    "cvv",
+    "cookie",
+];
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// Lowercase, found anywhere in the lowercased key.
+    Name(String),
+    Pattern(Regex),
+}
+
+impl Matcher {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Matcher::Name(name) => key.to_lowercase().contains(name.as_str()),
+            Matcher::Pattern(pattern) => pattern.is_match(key),
+        }
+    }
+}
+
+/// A filter replacing the values of sensitive keys in `params`, `session` and
+/// `environment` with `[Filtered]`.
+///
+/// A key is sensitive when it is on the blocklist or, once anything has been allowed,
+/// when it is not on the allowlist.
+#[derive(Debug, Clone)]
+pub struct Redact {
+    blocklist: Vec<Matcher>,
+    allowlist: Vec<Matcher>,
+    scrub_text: bool,
+    assignment: Regex,
+}
+
+impl Redact {
+    /// Blocks `DEFAULT_SENSITIVE_KEYS`.
+    pub fn new() -> Self {
+        DEFAULT_SENSITIVE_KEYS
+            .iter()
+            .fold(Self::empty(), |redact, key| redact.block(key))
+    }
+
+    /// Blocks nothing.
+    pub fn empty() -> Self {
+        Self {
+            blocklist: vec![],
+            allowlist: vec![],
+            scrub_text: false,
+            assignment: Regex::new(r#"([\w.\-]+)(\s*[=:]\s*)("[^"]*"|'[^']*'|[^\s&,;"']+)"#)
+                .unwrap(),
+        }
+    }
+
+    /// Blocks the keys containing `key`, ignoring case.
+    pub fn block(mut self, key: &str) -> Self {
+        self.blocklist.push(Matcher::Name(key.to_lowercase()));
+        self
+    }
+
+    /// Blocks the keys matching the regular expression.
+    pub fn block_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.blocklist.push(Matcher::Pattern(Regex::new(pattern)?));
+        Ok(self)
+    }
+
+    /// Keeps the keys containing `key`, ignoring case, and blocks all others.
+    pub fn allow(mut self, key: &str) -> Self {
+        self.allowlist.push(Matcher::Name(key.to_lowercase()));
+        self
+    }
+
+    /// Keeps the keys matching the regular expression and blocks all others.
+    pub fn allow_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.allowlist.push(Matcher::Pattern(Regex::new(pattern)?));
+        Ok(self)
+    }
+
+    /// Also redacts `key=value` and `key: value` pairs in the error messages and the
+    /// query string of `context.url`.
+    pub fn scrub_text(mut self, scrub_text: bool) -> Self {
+        self.scrub_text = scrub_text;
+        self
+    }
+
+    fn is_sensitive(&self, key: &str) -> bool {
+        self.blocklist.iter().any(|matcher| matcher.matches(key))
+            || (!self.allowlist.is_empty()
+                && !self.allowlist.iter().any(|matcher| matcher.matches(key)))
+    }
+
+    pub(crate) fn redact_map(&self, map: &mut HashMap<String, Value>) {
+        for (key, value) in map.iter_mut() {
+            self.redact_entry(key, value);
+        }
+    }
+
+    /// Redacts the value of a sensitive key, or the sensitive keys nested in it.
+    fn redact_entry(&self, key: &str, value: &mut Value) {
+        if self.is_sensitive(key) {
+            *value = Value::String(FILTERED.to_owned());
+            return;
+        }
+        self.redact_value(value);
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    self.redact_entry(key, value);
+                }
+            }
+            Value::Array(items) => {
+                for item in items.iter_mut() {
+                    self.redact_value(item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn redact_text(&self, text: &str) -> String {
+        self.assignment
+            .replace_all(text, |captures: &Captures<'_>| {
+                if self.is_sensitive(&captures[1]) {
+                    format!("{}{}{}", &captures[1], &captures[2], FILTERED)
+                } else {
+                    captures[0].to_owned()
+                }
+            })
+            .into_owned()
+    }
+
+    pub(crate) fn redact_url(&self, url: &str) -> String {
+        let (base, rest) = match url.split_once('?') {
+            Some(parts) => parts,
+            None => return url.to_owned(),
+        };
+        let (query, fragment) = match rest.split_once('#') {
+            Some((query, fragment)) => (query, Some(fragment)),
+            None => (rest, None),
+        };
+        let query: Vec<String> = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if self.is_sensitive(key) => format!("{key}={FILTERED}"),
+                _ => pair.to_owned(),
+            })
+            .collect();
+        let mut url = format!("{}?{}", base, query.join("&"));
+        if let Some(fragment) = fragment {
+            url.push('#');
+            url.push_str(fragment);
+        }
+        url
+    }
+}
+
+impl Default for Redact {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filter for Redact {
+    fn filter(&self, mut notice: Notice) -> Option<Notice> {
+        for map in [
+            &mut notice.params,
+            &mut notice.session,
+            &mut notice.environment,
+        ]
+        .iter_mut()
+        .filter_map(|map| map.as_mut())
+        {
+            self.redact_map(map);
+        }
+        if self.scrub_text {
+            for error in notice.errors.iter_mut() {
+                error.message = self.redact_text(&error.message);
+            }
+            notice.context.url = notice
+                .context
+                .url
+                .as_deref()
+                .map(|url| self.redact_url(url));
+        }
+        Some(notice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Redact, FILTERED};
+    use crate::test_util::notice;
+    use crate::{Filter, Notice, Result};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    fn map(pairs: &[(&str, &str)]) -> Option<HashMap<String, Value>> {
+        Some(
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), Value::from(*value)))
+                .collect(),
+        )
+    }
+
+    fn sensitive_notice() -> Notice {
+        let mut notice = notice("login failed for user=bob password=hunter2, token: \"abc\"");
+        notice.params = map(&[("user", "bob"), ("Password", "hunter2"), ("id", "1")]);
+        notice.session = map(&[("X-Auth-Token", "abc")]);
+        notice.environment = map(&[("HOME", "/root"), ("AWS_SECRET_ACCESS_KEY", "xyz")]);
+        notice.context.url = Some("https://example.com/login?user=bob&api_key=xyz#top".to_owned());
+        notice
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let notice = Redact::new().filter(sensitive_notice()).unwrap();
+        let params = notice.params.unwrap();
+        assert_eq!(params["user"], "bob");
+        assert_eq!(params["Password"], FILTERED);
+        assert_eq!(notice.session.unwrap()["X-Auth-Token"], FILTERED);
+        let environment = notice.environment.unwrap();
+        assert_eq!(environment["HOME"], "/root");
+        assert_eq!(environment["AWS_SECRET_ACCESS_KEY"], FILTERED);
+        // text is left alone unless asked for
+        assert!(notice.errors[0].message.contains("hunter2"));
+        assert!(notice.context.url.unwrap().contains("api_key=xyz"));
+    }
+
+    #[test]
+    fn test_nested_values() {
+        let mut notice = sensitive_notice();
+        notice
+            .set_param(
+                "body",
+                json!({"user": {"name": "bob", "password": "hunter2"}, "cards": [{"card_number": "4111"}]}),
+            )
+            .unwrap();
+        let notice = Redact::new().filter(notice).unwrap();
+        let body = &notice.params.unwrap()["body"];
+        assert_eq!(body["user"]["name"], "bob");
+        assert_eq!(body["user"]["password"], FILTERED);
+        assert_eq!(body["cards"][0]["card_number"], FILTERED);
+    }
+
+    #[test]
+    fn test_patterns_and_allowlist() -> Result<()> {
+        let notice = Redact::empty()
+            .block_pattern("^(?i)id$")?
+            .filter(sensitive_notice())
+            .unwrap();
+        let params = notice.params.unwrap();
+        assert_eq!(params["id"], FILTERED);
+        assert_eq!(params["Password"], "hunter2");
+
+        let notice = Redact::empty()
+            .allow("user")
+            .allow_pattern("^id$")?
+            .filter(sensitive_notice())
+            .unwrap();
+        let params = notice.params.unwrap();
+        assert_eq!(params["user"], "bob");
+        assert_eq!(params["id"], "1");
+        assert_eq!(params["Password"], FILTERED);
+        assert_eq!(notice.environment.unwrap()["HOME"], FILTERED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scrub_text() {
+        let notice = Redact::new()
+            .scrub_text(true)
+            .filter(sensitive_notice())
+            .unwrap();
+        assert_eq!(
+            notice.errors[0].message,
+            "login failed for user=bob password=[Filtered], token: [Filtered]"
+        );
+        assert_eq!(
+            notice.context.url.as_deref(),
+            Some("https://example.com/login?user=bob&api_key=[Filtered]#top")
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{NoticeBuilder, Notifier};
+use futures::future::BoxFuture;
+
+/// Reports the error of a `Result` with a std error and passes the result through.
+///
+/// In queued mode the notice is only handed over to the queue; otherwise it is sent
+/// before the result is returned. Failures to report are ignored.
+///
+/// ```no_run
+/// # async fn run(notifier: errbit::Notifier) -> Result<(), std::num::ParseIntError> {
+/// use errbit::ResultExt;
+///
+/// let amount: u32 = "12".parse().report(&notifier).await?;
+/// let amount: u32 = "12"
+///     .parse()
+///     .report_with(&notifier, |notice| notice.component("billing"))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait ResultExt: Sized {
+    fn report<'a>(self, notifier: &'a Notifier) -> BoxFuture<'a, Self>
+    where
+        Self: 'a;
+
+    /// Like `report`, with the notice completed by `build`.
+    fn report_with<'a, F>(self, notifier: &'a Notifier, build: F) -> BoxFuture<'a, Self>
+    where
+        Self: 'a,
+        F: FnOnce(NoticeBuilder) -> NoticeBuilder;
+
+    /// Reports without waiting, also from code outside of a tokio runtime.
+    fn report_detached(self, notifier: &Notifier) -> Self;
+}
+
+/// `ResultExt` for `anyhow::Result`.
+pub trait AnyhowResultExt: Sized {
+    fn report<'a>(self, notifier: &'a Notifier) -> BoxFuture<'a, Self>
+    where
+        Self: 'a;
+
+    /// Like `report`, with the notice completed by `build`.
+    fn report_with<'a, F>(self, notifier: &'a Notifier, build: F) -> BoxFuture<'a, Self>
+    where
+        Self: 'a,
+        F: FnOnce(NoticeBuilder) -> NoticeBuilder;
+
+    /// Reports without waiting, also from code outside of a tokio runtime.
+    fn report_detached(self, notifier: &Notifier) -> Self;
+}
+
+impl<T, E> ResultExt for Result<T, E>
+where
+    T: Send,
+    E: std::error::Error + Send,
+{
+    fn report<'a>(self, notifier: &'a Notifier) -> BoxFuture<'a, Self>
+    where
+        Self: 'a,
+    {
+        self.report_with(notifier, |builder| builder)
+    }
+
+    fn report_with<'a, F>(self, notifier: &'a Notifier, build: F) -> BoxFuture<'a, Self>
+    where
+        Self: 'a,
+        F: FnOnce(NoticeBuilder) -> NoticeBuilder,
+    {
+        let notice = self
+            .as_ref()
+            .err()
+            .map(|error| build(NoticeBuilder::from_std_error(error).config(notifier.config())));
+        report(self, notifier, notice)
+    }
+
+    fn report_detached(self, notifier: &Notifier) -> Self {
+        if let Err(error) = &self {
+            let notice = NoticeBuilder::from_std_error(error)
+                .config(notifier.config())
+                .build();
+            notifier.notify_detached(notice);
+        }
+        self
+    }
+}
+
+impl<T: Send> AnyhowResultExt for anyhow::Result<T> {
+    fn report<'a>(self, notifier: &'a Notifier) -> BoxFuture<'a, Self>
+    where
+        Self: 'a,
+    {
+        self.report_with(notifier, |builder| builder)
+    }
+
+    fn report_with<'a, F>(self, notifier: &'a Notifier, build: F) -> BoxFuture<'a, Self>
+    where
+        Self: 'a,
+        F: FnOnce(NoticeBuilder) -> NoticeBuilder,
+    {
+        let notice = self
+            .as_ref()
+            .err()
+            .map(|error| build(NoticeBuilder::from_anyhow_error(error).config(notifier.config())));
+        report(self, notifier, notice)
+    }
+
+    fn report_detached(self, notifier: &Notifier) -> Self {
+        if let Err(error) = &self {
+            let notice = NoticeBuilder::from_anyhow_error(error)
+                .config(notifier.config())
+                .build();
+            notifier.notify_detached(notice);
+        }
+        self
+    }
+}
+
+fn report<'a, R: Send + 'a>(
+    result: R,
+    notifier: &'a Notifier,
+    notice: Option<NoticeBuilder>,
+) -> BoxFuture<'a, R> {
+    Box::pin(async move {
+        if let Some(notice) = notice {
+            notifier.report(notice.build()).await;
+        }
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnyhowResultExt, ResultExt};
+    use crate::test_util::{created, StubServer};
+    use crate::{Notifier, QueueConfig, Result};
+    use std::time::Duration;
+
+    fn parse(number: &str) -> std::result::Result<u32, std::num::ParseIntError> {
+        number.parse()
+    }
+
+    #[tokio::test]
+    async fn test_report() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        assert_eq!(parse("7").report(&notifier).await, Ok(7));
+        assert_eq!(server.request_count(), 0);
+        assert!(parse("seven").report(&notifier).await.is_err());
+        assert_eq!(server.request_count(), 1);
+
+        let result: anyhow::Result<u32> = Err(anyhow::anyhow!("no luck"));
+        let err = result
+            .report_with(&notifier, |notice| notice.component("billing"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no luck");
+        let notice = server.notices()?.remove(1);
+        assert_eq!(notice.errors[0].message, "no luck");
+        assert_eq!(notice.context.component.as_deref(), Some("billing"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_report_queued_and_detached() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let mut config = server.config();
+        config.queue = Some(QueueConfig::default());
+        let notifier = Notifier::new(config)?;
+        assert!(parse("seven").report(&notifier).await.is_err());
+        assert!(parse("eight").report_detached(&notifier).is_err());
+        let report = notifier.flush(Duration::from_secs(5)).await;
+        assert_eq!(report.delivered, 2);
+        assert_eq!(server.request_count(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::Error;
+use rand::Rng;
+use std::time::Duration;
+
+/// Controls how often and how patiently `Client` resends a notice that failed.
+///
+/// Attempt `n` (starting at 1) waits `base_delay * 2^(n - 1)` capped at `max_delay`
+/// before the next one. With `jitter` the wait is picked at random from the upper
+/// half of that range so that many clients failing together do not retry in lockstep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+    /// Retry when the connection could not be established or was cut off.
+    pub retry_connection_errors: bool,
+    /// Retry on `5xx` responses.
+    pub retry_server_errors: bool,
+    /// Retry on `429 Too Many Requests`. `Client` pauses after a `429` (see
+    /// `Error::RateLimited`) and retries once the pause is over, unless it lasts longer
+    /// than `max_delay`.
+    pub retry_too_many_requests: bool,
+    /// How long `Client` pauses after a `429` without a readable `Retry-After`.
+    pub rate_limit_pause: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            retry_connection_errors: true,
+            retry_server_errors: true,
+            retry_too_many_requests: false,
+            rate_limit_pause: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sends every notice exactly once.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter && !delay.is_zero() {
+            let half = delay / 2;
+            half + rand::thread_rng().gen_range(Duration::from_millis(0)..=delay - half)
+        } else {
+            delay
+        }
+    }
+
+    pub fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        if let Some(e) = error.downcast_ref::<Error>() {
+            return match e {
+                Error::Gateway {
+                    status_code: 429, ..
+                }
+                | Error::RateLimited { .. } => self.retry_too_many_requests,
+                Error::Gateway { status_code, .. } => {
+                    self.retry_server_errors && (500..600).contains(status_code)
+                }
+                _ => false,
+            };
+        }
+        if let Some(e) = error.downcast_ref::<hyper::Error>() {
+            return self.retry_connection_errors
+                && (e.is_connect() || e.is_closed() || e.is_incomplete_message());
+        }
+        self.retry_connection_errors && error.downcast_ref::<std::io::Error>().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use crate::Error;
+    use std::time::Duration;
+
+    fn gateway(status_code: u16) -> anyhow::Error {
+        Error::Gateway {
+            status_code,
+            reason: String::new(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(300));
+        assert_eq!(policy.delay(40), Duration::from_millis(300));
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable(&gateway(502)));
+        assert!(policy.is_retryable(&gateway(503)));
+        assert!(!policy.is_retryable(&gateway(429)));
+        assert!(!policy.is_retryable(&gateway(400)));
+        assert!(!policy.is_retryable(&gateway(422)));
+        assert!(!policy.is_retryable(&anyhow::anyhow!("invalid notice")));
+        let policy = RetryPolicy {
+            retry_server_errors: false,
+            retry_too_many_requests: true,
+            ..policy
+        };
+        assert!(!policy.is_retryable(&gateway(503)));
+        assert!(policy.is_retryable(&gateway(429)));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::value::to_value;
+use crate::{Notice, UserInfo};
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+
+tokio::task_local! {
+    static TASK_SCOPE: Scope;
+}
+
+thread_local! {
+    static THREAD_SCOPE: RefCell<Option<Scope>> = const { RefCell::new(None) };
+}
+
+/// Notice data set by `with_scope` or `with_scope_sync` for the code it runs, merged
+/// into every notice sent from there. What a notice sets itself is kept.
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    user: Option<UserInfo>,
+    component: Option<String>,
+    action: Option<String>,
+    route: Option<String>,
+    params: HashMap<String, Value>,
+    session: HashMap<String, Value>,
+}
+
+impl Scope {
+    pub fn set_user(&mut self, user: UserInfo) -> &mut Self {
+        self.user = Some(user);
+        self
+    }
+
+    pub fn set_component<S: Into<String>>(&mut self, component: S) -> &mut Self {
+        self.component = Some(component.into());
+        self
+    }
+
+    pub fn set_action<S: Into<String>>(&mut self, action: S) -> &mut Self {
+        self.action = Some(action.into());
+        self
+    }
+
+    pub fn set_route<S: Into<String>>(&mut self, route: S) -> &mut Self {
+        self.route = Some(route.into());
+        self
+    }
+
+    /// Sets `params[key]`.
+    pub fn set_param<K: Into<String>, T: Serialize>(&mut self, key: K, value: T) -> &mut Self {
+        self.params.insert(key.into(), to_value(value));
+        self
+    }
+
+    /// Sets `session[key]`.
+    pub fn set_session<K: Into<String>, T: Serialize>(&mut self, key: K, value: T) -> &mut Self {
+        self.session.insert(key.into(), to_value(value));
+        self
+    }
+
+    /// Fills in what the notice does not set already.
+    fn apply(&self, notice: &mut Notice) {
+        let context = &mut notice.context;
+        fill(&mut context.user, &self.user);
+        fill(&mut context.component, &self.component);
+        fill(&mut context.action, &self.action);
+        fill(&mut context.route, &self.route);
+        for (map, scoped) in [
+            (&mut notice.params, &self.params),
+            (&mut notice.session, &self.session),
+        ] {
+            if scoped.is_empty() {
+                continue;
+            }
+            let map = map.get_or_insert_with(HashMap::new);
+            for (key, value) in scoped {
+                map.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    }
+}
+
+/// Runs `future` in a scope made by `configure` from the one `with_scope` is called in,
+/// kept in a tokio task-local. Tasks spawned from the future do not inherit it.
+///
+/// ```no_run
+/// # async fn run(notifier: errbit::Notifier) {
+/// use errbit::UserInfo;
+///
+/// errbit::with_scope(
+///     |scope| {
+///         scope
+///             .set_user(UserInfo {
+///                 id: Some("7".to_owned()),
+///                 ..UserInfo::default()
+///             })
+///             .set_param("invoice_id", 42)
+///     },
+///     async {
+///         // every notice sent here has the user and `params.invoice_id`
+///         let _ = notifier
+///             .notify_message("Declined", "card declined", errbit::Severity::ERROR)
+///             .await;
+///     },
+/// )
+/// .await;
+/// # }
+/// ```
+pub fn with_scope<C, F>(configure: C, future: F) -> impl Future<Output = F::Output>
+where
+    C: FnOnce(&mut Scope) -> &mut Scope,
+    F: Future,
+{
+    let mut scope = current().unwrap_or_default();
+    configure(&mut scope);
+    TASK_SCOPE.scope(scope, future)
+}
+
+/// Like `with_scope` for synchronous code, keeping the scope in a thread-local while
+/// `f` runs. It wins over the task-local scope, which it starts from.
+pub fn with_scope_sync<C, F, R>(configure: C, f: F) -> R
+where
+    C: FnOnce(&mut Scope) -> &mut Scope,
+    F: FnOnce() -> R,
+{
+    let mut scope = current().unwrap_or_default();
+    configure(&mut scope);
+    let previous = THREAD_SCOPE.with(|current| current.replace(Some(scope)));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Puts the enclosing thread-local scope back, also when `f` panics.
+struct Restore(Option<Scope>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        let _ = THREAD_SCOPE.try_with(|current| current.replace(previous));
+    }
+}
+
+/// The thread-local scope, or else the task-local one.
+fn current() -> Option<Scope> {
+    THREAD_SCOPE
+        .try_with(|current| current.borrow().clone())
+        .ok()
+        .flatten()
+        .or_else(|| TASK_SCOPE.try_with(Scope::clone).ok())
+}
+
+/// Merges the current scope, if any, into the notice.
+pub(crate) fn apply_current(notice: &mut Notice) {
+    if let Some(scope) = current() {
+        scope.apply(notice);
+    }
+}
+
+fn fill<T: Clone>(field: &mut Option<T>, scoped: &Option<T>) {
+    if field.is_none() {
+        field.clone_from(scoped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{with_scope, with_scope_sync};
+    use crate::test_util::{created, StubServer};
+    use crate::{Notice, NoticeBuilder, Notifier, Result, UserInfo};
+    use std::time::Duration;
+
+    fn user(id: &str) -> UserInfo {
+        UserInfo {
+            id: Some(id.to_owned()),
+            ..UserInfo::default()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_with_scope() -> Result<()> {
+        let server = StubServer::start(created).await;
+        let notifier = Notifier::new(server.config())?;
+        let config = notifier.config().clone();
+        with_scope(
+            |scope| {
+                scope
+                    .set_user(user("7"))
+                    .set_component("billing")
+                    .set_param("invoice_id", 42)
+                    .set_param("attempt", 1)
+                    .set_session("cart", vec![1, 2])
+            },
+            async {
+                with_scope(|scope| scope.set_route("/charge"), async {
+                    let notice = NoticeBuilder::from_message("Declined", "card declined")
+                        .component("payments")
+                        .param("attempt", 2)
+                        .config(&config)
+                        .build();
+                    notifier.notify(notice).await.unwrap();
+                })
+                .await;
+                // outside of the inner scope, and in a task that does not inherit any
+                let notice = Notice::new_from_anyhow_error(&anyhow::anyhow!("outer"), &config);
+                notifier.notify_detached(notice);
+                let notice = Notice::new_from_anyhow_error(&anyhow::anyhow!("spawned"), &config);
+                let spawned = notifier.clone();
+                tokio::spawn(async move {
+                    spawned.notify_detached(notice);
+                })
+                .await
+                .unwrap();
+            },
+        )
+        .await;
+
+        notifier.flush(Duration::from_secs(5)).await;
+        let notices = server.notices()?;
+        assert_eq!(notices.len(), 3);
+        let find = |message: &str| {
+            notices
+                .iter()
+                .find(|notice| notice.errors[0].message == message)
+                .unwrap()
+        };
+        let inner = find("card declined");
+        assert_eq!(
+            inner.context.user.as_ref().unwrap().id.as_deref(),
+            Some("7")
+        );
+        assert_eq!(inner.context.component.as_deref(), Some("payments"));
+        assert_eq!(inner.context.route.as_deref(), Some("/charge"));
+        let params = inner.params.as_ref().unwrap();
+        assert_eq!(params["invoice_id"], 42);
+        assert_eq!(params["attempt"], 2);
+        assert_eq!(
+            inner.session.as_ref().unwrap()["cart"],
+            serde_json::json!([1, 2])
+        );
+        let outer = find("outer");
+        assert_eq!(outer.context.component.as_deref(), Some("billing"));
+        assert!(outer.context.route.is_none());
+        let spawned = find("spawned");
+        assert!(spawned.context.user.is_none());
+        assert!(spawned.params.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_scope_sync() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let server = runtime.block_on(StubServer::start(created));
+        let config = server.config();
+        let notifier = runtime.block_on(async { Notifier::new(config.clone()) })?;
+        with_scope_sync(
+            |scope| scope.set_user(user("7")).set_action("import"),
+            || {
+                with_scope_sync(
+                    |scope| scope.set_param("row", 3),
+                    || {
+                        let notice =
+                            Notice::new_from_anyhow_error(&anyhow::anyhow!("bad row"), &config);
+                        notifier.notify_blocking(notice, Duration::from_secs(5))
+                    },
+                )?;
+                let notice =
+                    Notice::new_from_anyhow_error(&anyhow::anyhow!("import failed"), &config);
+                notifier.notify_blocking(notice, Duration::from_secs(5))
+            },
+        )?;
+        let notice = Notice::new_from_anyhow_error(&anyhow::anyhow!("unscoped"), &config);
+        notifier.notify_blocking(notice, Duration::from_secs(5))?;
+
+        let notices = server.notices()?;
+        assert_eq!(notices[0].params.as_ref().unwrap()["row"], 3);
+        assert_eq!(notices[0].context.action.as_deref(), Some("import"));
+        assert!(notices[1].params.is_none());
+        assert_eq!(
+            notices[1].context.user.as_ref().unwrap().id.as_deref(),
+            Some("7")
+        );
+        assert!(notices[2].context.user.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{BacktraceInfo, Notice};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// How much source code is attached to the backtrace frames of application files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceContextConfig {
+    /// Lines included before and after the line of the frame.
+    pub lines: usize,
+    /// Files larger than this are never read.
+    pub max_file_size: u64,
+    /// Longer lines are cut to this many characters.
+    pub max_line_length: usize,
+    /// How many files are kept in memory.
+    pub cache_size: usize,
+}
+
+impl Default for SourceContextConfig {
+    fn default() -> Self {
+        Self {
+            lines: 3,
+            max_file_size: 1024 * 1024,
+            max_line_length: 200,
+            cache_size: 32,
+        }
+    }
+}
+
+/// Fills `BacktraceInfo::code` from the files under the application root directory.
+#[derive(Debug)]
+pub(crate) struct SourceCache {
+    root: PathBuf,
+    config: SourceContextConfig,
+    files: Mutex<Files>,
+}
+
+/// Contents by path, with `None` remembering files that could not be used.
+#[derive(Debug, Default)]
+struct Files {
+    contents: HashMap<PathBuf, Option<Arc<Vec<String>>>>,
+    order: VecDeque<PathBuf>,
+}
+
+impl SourceCache {
+    pub fn new(root: impl Into<PathBuf>, config: SourceContextConfig) -> Self {
+        Self {
+            root: root.into(),
+            config,
+            files: Mutex::new(Files::default()),
+        }
+    }
+
+    /// Returns a copy of the notice with the code attached, reading the files on a
+    /// blocking thread so that the async workers never wait on the disk.
+    pub async fn annotated(self: &Arc<Self>, notice: &Notice) -> Option<Notice> {
+        let source = self.clone();
+        let mut notice = notice.clone();
+        tokio::task::spawn_blocking(move || {
+            source.annotate(&mut notice);
+            notice
+        })
+        .await
+        .ok()
+    }
+
+    pub fn annotate(&self, notice: &mut Notice) {
+        let frames = notice
+            .errors
+            .iter_mut()
+            .filter_map(|error| error.backtrace.as_mut())
+            .flatten();
+        for frame in frames {
+            if frame.code.is_none() {
+                frame.code = self.snippet(frame);
+            }
+        }
+    }
+
+    fn snippet(&self, frame: &BacktraceInfo) -> Option<HashMap<String, String>> {
+        let line = frame.line.filter(|line| *line > 0)?;
+        let path = self.resolve(frame.file.as_deref()?)?;
+        let lines = self.lines(path)?;
+        if line > lines.len() {
+            return None;
+        }
+        let first = line.saturating_sub(self.config.lines).max(1);
+        let last = (line + self.config.lines).min(lines.len());
+        Some(
+            (first..=last)
+                .map(|number| (number.to_string(), lines[number - 1].clone()))
+                .collect(),
+        )
+    }
+
+    /// The file on disk, if it lies under the root directory. By the time a notice is
+    /// sent, these paths are already rewritten to `[PROJECT_ROOT]/...`.
+    fn resolve(&self, file: &str) -> Option<PathBuf> {
+        let file = file.strip_prefix("[PROJECT_ROOT]/").unwrap_or(file);
+        let path = self.root.join(file);
+        let escapes = path
+            .components()
+            .any(|component| component == Component::ParentDir);
+        if escapes || !path.starts_with(&self.root) {
+            return None;
+        }
+        Some(path)
+    }
+
+    fn lines(&self, path: PathBuf) -> Option<Arc<Vec<String>>> {
+        if let Some(lines) = self.files.lock().unwrap().contents.get(&path) {
+            return lines.clone();
+        }
+        let lines = self.read(&path).map(Arc::new);
+        let mut files = self.files.lock().unwrap();
+        if files.contents.insert(path.clone(), lines.clone()).is_none() {
+            files.order.push_back(path);
+        }
+        while files.order.len() > self.config.cache_size {
+            if let Some(oldest) = files.order.pop_front() {
+                files.contents.remove(&oldest);
+            }
+        }
+        lines
+    }
+
+    fn read(&self, path: &Path) -> Option<Vec<String>> {
+        let metadata = fs::metadata(path).ok()?;
+        if !metadata.is_file() || metadata.len() > self.config.max_file_size {
+            return None;
+        }
+        let contents = fs::read_to_string(path).ok()?;
+        Some(
+            contents
+                .lines()
+                .map(|line| line.chars().take(self.config.max_line_length).collect())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceCache, SourceContextConfig};
+    use crate::test_util::{created, notice, temp_dir, StubServer};
+    use crate::{BacktraceInfo, ErrorInfo, Notice, Notifier};
+    use std::fs;
+
+    fn notice_at(file: &str, line: usize) -> Notice {
+        let mut notice = notice("source test");
+        notice.errors = vec![ErrorInfo {
+            type_: "Error".to_owned(),
+            message: "source test".to_owned(),
+            backtrace: None,
+        }
+        .with_backtrace(vec![BacktraceInfo {
+            file: Some(file.to_owned()),
+            line: Some(line),
+            ..BacktraceInfo::default()
+        }])];
+        notice
+    }
+
+    fn code(cache: &SourceCache, file: &str, line: usize) -> Option<Vec<(usize, String)>> {
+        let mut notice = notice_at(file, line);
+        cache.annotate(&mut notice);
+        let backtrace = notice.errors.remove(0).backtrace.unwrap();
+        backtrace[0].code.as_ref().map(|code| {
+            let mut lines: Vec<(usize, String)> = code
+                .iter()
+                .map(|(number, line)| (number.parse().unwrap(), line.clone()))
+                .collect();
+            lines.sort();
+            lines
+        })
+    }
+
+    #[test]
+    fn test_annotate() {
+        let root = temp_dir("source");
+        fs::create_dir_all(root.join("src")).unwrap();
+        let contents: Vec<String> = (1..=10).map(|n| format!("line {n}")).collect();
+        fs::write(root.join("src/main.rs"), contents.join("\n")).unwrap();
+        fs::write(root.join("src/long.rs"), "x".repeat(50)).unwrap();
+        fs::write(root.join("src/large.rs"), "y\n".repeat(1000)).unwrap();
+        let cache = SourceCache::new(
+            &root,
+            SourceContextConfig {
+                lines: 2,
+                max_file_size: 1000,
+                max_line_length: 10,
+                cache_size: 2,
+            },
+        );
+
+        let lines = code(&cache, "src/main.rs", 5).unwrap();
+        let numbers: Vec<usize> = lines.iter().map(|(number, _)| *number).collect();
+        assert_eq!(numbers, vec![3, 4, 5, 6, 7]);
+        assert_eq!(lines[2].1, "line 5");
+        let numbers: Vec<usize> = code(&cache, "src/main.rs", 1)
+            .unwrap()
+            .iter()
+            .map(|(number, _)| *number)
+            .collect();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        let absolute = root.join("src/main.rs");
+        assert!(code(&cache, &absolute.display().to_string(), 10).is_some());
+        assert!(code(&cache, "src/main.rs", 11).is_none());
+        assert_eq!(
+            code(&cache, "[PROJECT_ROOT]/src/main.rs", 5).unwrap()[2].1,
+            "line 5"
+        );
+        // served from memory until evicted
+        fs::write(root.join("src/main.rs"), "changed").unwrap();
+        assert_eq!(code(&cache, "src/main.rs", 5).unwrap()[2].1, "line 5");
+
+        assert_eq!(code(&cache, "src/long.rs", 1).unwrap()[0].1, "x".repeat(10));
+        assert!(code(&cache, "src/large.rs", 1).is_none());
+        assert!(code(&cache, "src/missing.rs", 1).is_none());
+        assert!(code(&cache, "../outside.rs", 1).is_none());
+        assert!(code(&cache, "/etc/hostname", 1).is_none());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_notifier_attaches_code() -> crate::Result<()> {
+        let root = temp_dir("notifier-source");
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/main.rs"), "fn main() {\n    fail();\n}\n")?;
+        let server = StubServer::start(created).await;
+        let mut config = server.config();
+        config.app_root_directory = Some(root.display().to_string());
+        assert!(config.source_context.is_none());
+        config.source_context = Some(SourceContextConfig::default());
+        let notifier = Notifier::new(config)?;
+        notifier.notify(notice_at("src/main.rs", 2)).await?;
+        let sent = server.notices()?.remove(0);
+        let backtrace = sent.errors[0].backtrace.as_ref().unwrap();
+        assert_eq!(
+            backtrace[0].file.as_deref(),
+            Some("[PROJECT_ROOT]/src/main.rs")
+        );
+        let code = backtrace[0].code.as_ref().unwrap();
+        assert_eq!(code["2"], "    fail();");
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+}
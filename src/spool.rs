@@ -0,0 +1,436 @@
+use crate::{Client, Error, Notice, NotifyResult, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const EXTENSION: &str = "json";
+
+/// Where and how much to keep of notices that could not be delivered.
+///
+/// Each notice is stored as its own `<timestamp>-<pid>-<sequence>.json` file. When a cap
+/// is exceeded the oldest files are removed first. Other files in the directory are left
+/// alone, so it can be shared with the application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpoolConfig {
+    pub directory: PathBuf,
+    pub max_files: usize,
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+impl SpoolConfig {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            max_files: 1000,
+            max_bytes: 10 * 1024 * 1024,
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Spool {
+    config: SpoolConfig,
+    sequence: Arc<AtomicU64>,
+    replaying: Arc<AtomicBool>,
+    /// Cleared by a replay that went through every entry, so that a delivery does not
+    /// have to list the directory to know whether there is anything to resend.
+    pending: Arc<AtomicBool>,
+    /// Kept up to date so that storing a notice only lists the directory once a cap is
+    /// exceeded.
+    usage: Arc<Mutex<Usage>>,
+}
+
+/// How many entries the directory holds and their total size.
+#[derive(Debug, Default)]
+struct Usage {
+    files: usize,
+    bytes: u64,
+}
+
+impl Spool {
+    /// Opens the directory, removing the entries that are too old or over the caps.
+    pub fn open(config: SpoolConfig) -> Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        let spool = Self {
+            config,
+            sequence: Arc::new(AtomicU64::new(0)),
+            replaying: Arc::new(AtomicBool::new(false)),
+            pending: Arc::new(AtomicBool::new(false)),
+            usage: Arc::new(Mutex::new(Usage::default())),
+        };
+        spool.prune()?;
+        let pending = spool.usage.lock().unwrap().files > 0;
+        spool.pending.store(pending, Ordering::SeqCst);
+        Ok(spool)
+    }
+
+    pub fn store(&self, notice: &Notice) -> Result<PathBuf> {
+        let name = format!(
+            "{:020}-{}-{:010}",
+            unix_millis(SystemTime::now()),
+            std::process::id(),
+            self.sequence.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = self.config.directory.join(&name).with_extension(EXTENSION);
+        let partial = self.config.directory.join(&name).with_extension("partial");
+        let json = notice.to_json();
+        fs::write(&partial, &json)?;
+        fs::rename(&partial, &path)?;
+        self.pending.store(true, Ordering::SeqCst);
+        let over_cap = {
+            let mut usage = self.usage.lock().unwrap();
+            usage.files += 1;
+            usage.bytes += json.len() as u64;
+            usage.files > self.config.max_files || usage.bytes > self.config.max_bytes
+        };
+        if over_cap {
+            self.prune()?;
+        }
+        Ok(path)
+    }
+
+    /// Spooled notices, oldest first.
+    pub fn entries(&self) -> Result<Vec<PathBuf>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.config.directory)? {
+            let path = entry?.path();
+            if is_entry(&path) {
+                entries.push(path);
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Removes the entries that are too old, then the oldest ones until the caps are met.
+    fn prune(&self) -> Result<()> {
+        let oldest_kept = SystemTime::now()
+            .checked_sub(self.config.max_age)
+            .map_or(0, unix_millis);
+        let mut entries = vec![];
+        for path in self.entries()? {
+            match stored_at(&path) {
+                Some(stored_at) if stored_at >= oldest_kept => {
+                    let size = fs::metadata(&path)
+                        .map(|metadata| metadata.len())
+                        .unwrap_or(0);
+                    entries.push((path, size));
+                }
+                _ => remove(&path),
+            }
+        }
+        let mut total_bytes: u64 = entries.iter().map(|(_, size)| size).sum();
+        let mut count = entries.len();
+        for (path, size) in entries {
+            if count <= self.config.max_files && total_bytes <= self.config.max_bytes {
+                break;
+            }
+            remove(&path);
+            count -= 1;
+            total_bytes -= size;
+        }
+        *self.usage.lock().unwrap() = Usage {
+            files: count,
+            bytes: total_bytes,
+        };
+        Ok(())
+    }
+
+    /// Resends spooled notices oldest first and stops at the first one that still fails.
+    /// Returns how many were delivered, none when another replay is already running.
+    pub async fn replay(&self, client: &Client) -> Result<usize> {
+        match self.start_replay() {
+            Some(_guard) => self.replay_entries(client).await,
+            None => Ok(0),
+        }
+    }
+
+    /// Starts a replay on the current tokio runtime unless one is already running.
+    pub fn replay_in_background(&self, client: &Client) {
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        let guard = match self.start_replay() {
+            Some(guard) => guard,
+            None => return,
+        };
+        let spool = self.clone();
+        let client = client.clone();
+        runtime.spawn(async move {
+            let _guard = guard;
+            let _ = spool.replay_entries(&client).await;
+        });
+    }
+
+    fn start_replay(&self) -> Option<ReplayGuard> {
+        if self.replaying.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(ReplayGuard(self.replaying.clone()))
+    }
+
+    async fn replay_entries(&self, client: &Client) -> Result<usize> {
+        // cleared before listing, so that a notice stored meanwhile sets it again
+        self.pending.store(false, Ordering::SeqCst);
+        let result = self.resend(client).await;
+        if !matches!(result, Ok((_, true))) {
+            self.pending.store(true, Ordering::SeqCst);
+        }
+        result.map(|(delivered, _)| delivered)
+    }
+
+    /// Returns how many were delivered and whether every entry was dealt with.
+    async fn resend(&self, client: &Client) -> Result<(usize, bool)> {
+        self.prune()?;
+        let mut delivered = 0;
+        for path in self.entries()? {
+            let notice = match fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(Notice::from_json(&json)?))
+            {
+                Ok(notice) => notice,
+                Err(_) => {
+                    self.remove_entry(&path);
+                    continue;
+                }
+            };
+            match client.notify(&notice).await {
+                Ok(_) => {
+                    self.remove_entry(&path);
+                    delivered += 1;
+                }
+                Err(e) if is_transient(&e) => return Ok((delivered, false)),
+                Err(_) => self.remove_entry(&path),
+            }
+        }
+        Ok((delivered, true))
+    }
+
+    /// Keeps the notice when the endpoint looks unreachable, and resends earlier
+    /// leftovers once a delivery goes through again. Returns whether the notice was spooled.
+    ///
+    /// The notice is written on a blocking thread, off the async workers.
+    pub async fn track(
+        &self,
+        client: &Client,
+        notice: &Notice,
+        result: &Result<NotifyResult>,
+    ) -> bool {
+        match result {
+            Ok(_) => {
+                if self.pending.load(Ordering::SeqCst) {
+                    self.replay_in_background(client);
+                }
+                false
+            }
+            Err(e) if is_transient(e) => {
+                let spool = self.clone();
+                let notice = notice.clone();
+                let stored = tokio::task::spawn_blocking(move || spool.store(&notice)).await;
+                matches!(stored, Ok(Ok(_)))
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn remove_entry(&self, path: &Path) {
+        let size = fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        if fs::remove_file(path).is_ok() {
+            let mut usage = self.usage.lock().unwrap();
+            usage.files = usage.files.saturating_sub(1);
+            usage.bytes = usage.bytes.saturating_sub(size);
+        }
+    }
+}
+
+/// Marks the replay as finished when dropped, also when it fails or is cancelled.
+struct ReplayGuard(Arc<AtomicBool>);
+
+impl Drop for ReplayGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Failures that may go away by themselves, as opposed to a notice the server refuses.
+fn is_transient(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<Error>() {
+        Some(Error::RateLimited { .. }) | Some(Error::Timeout { .. }) => true,
+        Some(Error::Gateway { status_code, .. }) => {
+            *status_code == 429 || (500..600).contains(status_code)
+        }
+        Some(_) => false,
+        None => {
+            error.downcast_ref::<hyper::Error>().is_some()
+                || error.downcast_ref::<std::io::Error>().is_some()
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0)
+}
+
+/// Whether the file is named like the ones `Spool::store` writes.
+fn is_entry(path: &Path) -> bool {
+    let stem = match path.file_stem().and_then(OsStr::to_str) {
+        Some(stem) if path.extension() == Some(OsStr::new(EXTENSION)) => stem,
+        _ => return false,
+    };
+    let parts: Vec<&str> = stem.split('-').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+fn stored_at(path: &Path) -> Option<u128> {
+    path.file_stem()?.to_str()?.split('-').next()?.parse().ok()
+}
+
+fn remove(path: &Path) {
+    let _ = fs::remove_file(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unix_millis, Spool, SpoolConfig};
+    use crate::test_util::{created, notice, status, temp_dir, StubServer};
+    use crate::{Client, Notice, Notifier, Result, RetryPolicy};
+    use hyper::{Body, Response};
+
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_store_and_caps() -> Result<()> {
+        let mut config = SpoolConfig::new(temp_dir("spool-caps"));
+        config.max_files = 3;
+        let spool = Spool::open(config.clone())?;
+        for i in 0..5 {
+            spool.store(&notice(&format!("notice {i}")))?;
+        }
+        let entries = spool.entries()?;
+        assert_eq!(entries.len(), 3);
+        let oldest = Notice::from_json(&std::fs::read_to_string(&entries[0])?)?;
+        assert_eq!(oldest.errors[0].message, "notice 2");
+
+        config.max_bytes = std::fs::metadata(&entries[0])?.len();
+        let spool = Spool::open(config.clone())?;
+        spool.store(&notice("notice 5"))?;
+        assert_eq!(spool.entries()?.len(), 1);
+
+        config.max_age = Duration::from_millis(0);
+        let spool = Spool::open(config.clone())?;
+        std::thread::sleep(Duration::from_millis(5));
+        spool.prune()?;
+        assert!(spool.entries()?.is_empty());
+        std::fs::remove_dir_all(&config.directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay() -> Result<()> {
+        let config = SpoolConfig::new(temp_dir("spool-replay"));
+        let spool = Spool::open(config.clone())?;
+        for i in 0..3 {
+            spool.store(&notice(&format!("notice {i}")))?;
+        }
+        let stored_at = unix_millis(SystemTime::now() - Duration::from_secs(1));
+        let corrupt = config
+            .directory
+            .join(format!("{stored_at:020}-1-0000000000.json"));
+        std::fs::write(&corrupt, "{")?;
+        let foreign = config.directory.join("settings.json");
+        std::fs::write(&foreign, "{")?;
+        assert_eq!(spool.entries()?.len(), 4);
+
+        let server = StubServer::start(|n| if n == 1 { status(503) } else { created(n) }).await;
+        let client = Client::new(server.config().endpoint().as_str())?
+            .with_retry_policy(RetryPolicy::none());
+        assert_eq!(spool.replay(&client).await?, 1);
+        assert_eq!(spool.entries()?.len(), 2);
+        assert_eq!(spool.replay(&client).await?, 2);
+        assert!(spool.entries()?.is_empty());
+        // spooled notices that cannot be read are dropped, other files are kept
+        assert!(!corrupt.exists());
+        assert!(foreign.exists());
+        std::fs::remove_dir_all(&config.directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_replays() -> Result<()> {
+        let config = SpoolConfig::new(temp_dir("spool-concurrent"));
+        let spool = Spool::open(config.clone())?;
+        for i in 0..2 {
+            spool.store(&notice(&format!("notice {i}")))?;
+        }
+        let server = StubServer::start_with_delay(Duration::from_millis(100), created).await;
+        let client = Client::new(server.config().endpoint().as_str())?
+            .with_retry_policy(RetryPolicy::none());
+        spool.replay_in_background(&client);
+        assert_eq!(spool.replay(&client).await?, 0);
+        for _ in 0..50 {
+            if spool.entries()?.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(spool.entries()?.is_empty());
+        assert_eq!(server.request_count(), 2);
+        assert!(!spool.pending.load(std::sync::atomic::Ordering::SeqCst));
+        std::fs::remove_dir_all(&config.directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notifier_spools_failed_notice() -> Result<()> {
+        let spool_config = SpoolConfig::new(temp_dir("spool-notifier"));
+        let server = StubServer::start(|n| if n == 0 { status(503) } else { created(n) }).await;
+        let mut config = server.config();
+        config.retry = RetryPolicy::none();
+        config.spool = Some(spool_config.clone());
+        let notifier = Notifier::new(config)?;
+        assert!(notifier.notify(notice("lost")).await.is_err());
+        assert_eq!(Spool::open(spool_config.clone())?.entries()?.len(), 1);
+        assert_eq!(notifier.replay_spool().await?, 1);
+        assert!(Spool::open(spool_config.clone())?.entries()?.is_empty());
+        std::fs::remove_dir_all(&spool_config.directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notifier_skips_notices_turned_away_by_rate_limit() -> Result<()> {
+        let spool_config = SpoolConfig::new(temp_dir("spool-rate-limited"));
+        let server = StubServer::start(|_| {
+            Response::builder()
+                .status(429)
+                .header("Retry-After", "60")
+                .body(Body::from("slow down"))
+                .unwrap()
+        })
+        .await;
+        let mut config = server.config();
+        config.retry = RetryPolicy::none();
+        config.spool = Some(spool_config.clone());
+        let notifier = Notifier::new(config)?;
+        for message in ["first", "second", "third"] {
+            assert!(notifier.notify(notice(message)).await.is_err());
+        }
+        // only the notice the server answered is kept
+        assert_eq!(server.request_count(), 1);
+        assert_eq!(Spool::open(spool_config.clone())?.entries()?.len(), 1);
+        std::fs::remove_dir_all(&spool_config.directory)?;
+        Ok(())
+    }
+}
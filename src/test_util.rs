@@ -0,0 +1,147 @@
+use crate::{Config, Notice};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Respond = dyn Fn(usize) -> Response<Body> + Send + Sync;
+
+#[derive(Default)]
+struct Stats {
+    bodies: Mutex<Vec<String>>,
+    requests: AtomicUsize,
+    connections: AtomicUsize,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+/// A local errbit endpoint that answers the n-th request (starting at 0) with `respond(n)`.
+pub struct StubServer {
+    addr: SocketAddr,
+    stats: Arc<Stats>,
+}
+
+impl StubServer {
+    pub async fn start<F>(respond: F) -> Self
+    where
+        F: Fn(usize) -> Response<Body> + Send + Sync + 'static,
+    {
+        Self::start_with_delay(Duration::from_millis(0), respond).await
+    }
+
+    pub async fn start_with_delay<F>(delay: Duration, respond: F) -> Self
+    where
+        F: Fn(usize) -> Response<Body> + Send + Sync + 'static,
+    {
+        let respond: Arc<Respond> = Arc::new(respond);
+        let stats = Arc::new(Stats::default());
+        let make_service = {
+            let stats = stats.clone();
+            make_service_fn(move |_| {
+                let respond = respond.clone();
+                let stats = stats.clone();
+                stats.connections.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        let respond = respond.clone();
+                        let stats = stats.clone();
+                        async move {
+                            let body = hyper::body::to_bytes(request.into_body())
+                                .await
+                                .unwrap_or_default();
+                            stats
+                                .bodies
+                                .lock()
+                                .unwrap()
+                                .push(String::from_utf8_lossy(&body).into_owned());
+                            let n = stats.requests.fetch_add(1, Ordering::SeqCst);
+                            let in_flight = stats.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                            stats.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                            tokio::time::sleep(delay).await;
+                            stats.in_flight.fetch_sub(1, Ordering::SeqCst);
+                            Ok::<_, Infallible>(respond(n))
+                        }
+                    }))
+                }
+            })
+        };
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        Self { addr, stats }
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            host: format!("http://{}", self.addr),
+            project_id: "1".to_owned(),
+            project_key: "key".to_owned(),
+            ..Config::default()
+        }
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.stats.requests.load(Ordering::SeqCst)
+    }
+
+    pub fn bodies(&self) -> Vec<String> {
+        self.stats.bodies.lock().unwrap().clone()
+    }
+
+    /// The notices received so far, in order.
+    pub fn notices(&self) -> serde_json::Result<Vec<Notice>> {
+        self.bodies()
+            .iter()
+            .map(|body| Notice::from_json(body))
+            .collect()
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.stats.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn max_in_flight(&self) -> usize {
+        self.stats.max_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// A notice for a message-only error, built with the default configuration.
+pub fn notice(message: &str) -> Notice {
+    Notice::new_from_anyhow_error(&anyhow::Error::msg(message.to_owned()), &Config::default())
+}
+
+/// An empty path under the system temp directory, unique to the process and the call,
+/// so that tests running in parallel never share one.
+pub fn temp_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "errbit-{}-{}-{}",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+pub fn created(n: usize) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .body(Body::from(format!(
+            r#"{{"id":"{}","url":"http://errbit.example.com/notices/{}"}}"#,
+            n + 1,
+            n + 1
+        )))
+        .unwrap()
+}
+
+pub fn status(status_code: u16) -> Response<Body> {
+    Response::builder()
+        .status(status_code)
+        .body(Body::from("stub error"))
+        .unwrap()
+}
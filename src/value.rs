@@ -0,0 +1,134 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// What values nested deeper than `ValueLimits::max_depth` are replaced with.
+pub const TRUNCATED: &str = "[Truncated]";
+
+/// Bounds for the values of `params`, `session` and `environment`, so that a large
+/// request body does not make the whole notice too large to be accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueLimits {
+    /// Arrays and objects nested deeper are replaced with `[Truncated]`.
+    pub max_depth: usize,
+    /// Arrays and objects keep at most this many items.
+    pub max_items: usize,
+    /// Longer strings are cut to this many characters.
+    pub max_string_length: usize,
+}
+
+impl Default for ValueLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 5,
+            max_items: 100,
+            max_string_length: 1024,
+        }
+    }
+}
+
+/// The JSON value of `value`, or the error message if it cannot be serialized.
+pub(crate) fn to_value<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).unwrap_or_else(|e| Value::String(e.to_string()))
+}
+
+impl ValueLimits {
+    /// Truncates the value in place, counting its own level as depth 1.
+    pub fn truncate(&self, value: &mut Value) {
+        self.truncate_at(value, 1);
+    }
+
+    /// Truncates a map of the notice as if it were an object value.
+    pub(crate) fn truncate_map(&self, map: &mut HashMap<String, Value>) {
+        if map.len() > self.max_items {
+            let mut keys: Vec<String> = map.keys().cloned().collect();
+            keys.sort();
+            for key in keys.into_iter().skip(self.max_items) {
+                map.remove(&key);
+            }
+        }
+        for item in map.values_mut() {
+            self.truncate_at(item, 2);
+        }
+    }
+
+    fn truncate_at(&self, value: &mut Value, depth: usize) {
+        match value {
+            Value::Array(_) | Value::Object(_) if depth > self.max_depth => {
+                *value = Value::String(TRUNCATED.to_owned());
+            }
+            Value::Array(items) => {
+                items.truncate(self.max_items);
+                for item in items.iter_mut() {
+                    self.truncate_at(item, depth + 1);
+                }
+            }
+            Value::Object(map) => {
+                let extra: Vec<String> = map.keys().skip(self.max_items).cloned().collect();
+                for key in extra {
+                    map.remove(&key);
+                }
+                for item in map.values_mut() {
+                    self.truncate_at(item, depth + 1);
+                }
+            }
+            Value::String(string) => {
+                if let Some((index, _)) = string.char_indices().nth(self.max_string_length) {
+                    string.truncate(index);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ValueLimits, TRUNCATED};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_truncate() {
+        let limits = ValueLimits {
+            max_depth: 2,
+            max_items: 3,
+            max_string_length: 4,
+        };
+        let mut value = json!({
+            "name": "käthe-long",
+            "items": [1, 2, 3, 4, 5],
+            "nested": {"deeper": {"deepest": true}, "flag": false},
+            "zzz": null,
+        });
+        limits.truncate(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "items": [1, 2, 3],
+                "name": "käth",
+                "nested": {"deeper": TRUNCATED, "flag": false},
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncate_map() {
+        let limits = ValueLimits {
+            max_depth: 1,
+            max_items: 2,
+            ..ValueLimits::default()
+        };
+        let mut map: HashMap<String, Value> = vec![
+            ("a".to_owned(), json!([1])),
+            ("b".to_owned(), json!("kept")),
+            ("c".to_owned(), json!("dropped")),
+        ]
+        .into_iter()
+        .collect();
+        limits.truncate_map(&mut map);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], TRUNCATED);
+        assert_eq!(map["b"], "kept");
+    }
+}